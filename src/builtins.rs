@@ -0,0 +1,348 @@
+use crate::{codegen::Codegen, memory::mem_arg};
+use wasm_encoder::*;
+
+/// The most bytes an i64 takes up as decimal text, `-9223372036854775808`
+pub const INT_MAX_DIGITS: i32 = 20;
+
+/// A function compiled into a sycamore program for generated code to call
+/// while it runs. Only the builtins a program uses end up in its module.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Builtin {
+  /// Formats an i64 as decimal text in memory
+  FmtInt,
+  /// Joins two strings into a new one on the heap, freeing the ones that
+  /// were only made to be joined
+  StrConcat,
+  /// Checks if two strings hold the same text, freeing the ones that were
+  /// only made to be compared
+  StrEq,
+  /// Allocates memory on the heap
+  Alloc,
+  /// Gives memory from `Alloc` back to the heap
+  Free,
+}
+
+impl Builtin {
+  /// The name of the function in the module
+  pub fn name(&self) -> &'static str {
+    match self {
+      Builtin::FmtInt => "__fmt_int",
+      Builtin::StrConcat => "__str_concat",
+      Builtin::StrEq => "__str_eq",
+      Builtin::Alloc => "__alloc",
+      Builtin::Free => "__free",
+    }
+  }
+
+  /// The parameters and results of the function
+  fn signature(&self) -> (Vec<ValType>, Vec<ValType>) {
+    match self {
+      Builtin::FmtInt => (vec![ValType::I64, ValType::I32], vec![ValType::I32]),
+      Builtin::StrConcat => (
+        vec![ValType::I64, ValType::I64, ValType::I32, ValType::I32],
+        vec![ValType::I64],
+      ),
+      Builtin::StrEq => (
+        vec![ValType::I64, ValType::I64, ValType::I32, ValType::I32],
+        vec![ValType::I32],
+      ),
+      Builtin::Alloc => (vec![ValType::I32], vec![ValType::I32]),
+      Builtin::Free => (vec![ValType::I32], Vec::new()),
+    }
+  }
+}
+
+/// Functions that get compiled into a sycamore program for generated code to
+/// call. A builtin is added to the module the first time code that calls it
+/// is generated, and its code is generated after all of the states.
+pub trait Builtins {
+  fn builtin(&mut self, builtin: Builtin) -> u32;
+  fn generate_builtins(&mut self);
+}
+
+impl Builtins for Codegen {
+  /// Get the function index of a builtin, adding it to the module if it is
+  /// not in it yet
+  fn builtin(&mut self, builtin: Builtin) -> u32 {
+    if let Some(idx) = self.fn_map.get(builtin.name()) {
+      return *idx;
+    }
+    let (params, results) = builtin.signature();
+    self.builtins.push(builtin);
+    self.register_function(builtin.name(), params, results)
+  }
+
+  /// Generate the code for every builtin that was used. A builtin that is
+  /// first used by another one is added while this runs and generated after
+  /// it.
+  fn generate_builtins(&mut self) {
+    let mut generated = 0;
+    while let Some(builtin) = self.builtins.get(generated).copied() {
+      match builtin {
+        Builtin::FmtInt => self.generate_fmt_int(),
+        Builtin::StrConcat => self.generate_str_concat(),
+        Builtin::StrEq => self.generate_str_eq(),
+        Builtin::Alloc => self.generate_alloc(),
+        Builtin::Free => self.generate_free(),
+      }
+      generated += 1;
+    }
+  }
+}
+
+impl Codegen {
+  /// Generate `__fmt_int(value, end) -> start` which writes `value` as decimal
+  /// text into the bytes directly before `end` and returns where the text
+  /// starts. Smaller integers are extended to an i64 before the call. Digits
+  /// are produced from the lowest up using signed division so that `i64::MIN`
+  /// does not overflow when it is made positive.
+  fn generate_fmt_int(&mut self) {
+    let idx = self.fn_map[Builtin::FmtInt.name()];
+    self.functions.function(idx);
+
+    let (value, end, ptr, digit, negative) = (0, 1, 2, 3, 4);
+    self.current_func = Some(Function::new_with_locals_types(vec![
+      ValType::I32,
+      ValType::I64,
+      ValType::I32,
+    ]));
+    let mem = MemArg {
+      memory_index: 0,
+      align: 0,
+      offset: 0,
+    };
+
+    self.instruction(Instruction::LocalGet(end));
+    self.instruction(Instruction::LocalSet(ptr));
+    self.instruction(Instruction::LocalGet(value));
+    self.instruction(Instruction::I64Const(0));
+    self.instruction(Instruction::I64LtS);
+    self.instruction(Instruction::LocalSet(negative));
+
+    self.instruction(Instruction::Loop(BlockType::Empty));
+    // ptr -= 1
+    self.instruction(Instruction::LocalGet(ptr));
+    self.instruction(Instruction::I32Const(1));
+    self.instruction(Instruction::I32Sub);
+    self.instruction(Instruction::LocalSet(ptr));
+    // digit = value % 10, which is negative for negative values
+    self.instruction(Instruction::LocalGet(value));
+    self.instruction(Instruction::I64Const(10));
+    self.instruction(Instruction::I64RemS);
+    self.instruction(Instruction::LocalSet(digit));
+    // *ptr = '0' + (negative ? -digit : digit)
+    self.instruction(Instruction::LocalGet(ptr));
+    self.instruction(Instruction::I64Const('0' as i64));
+    self.instruction(Instruction::I64Const(0));
+    self.instruction(Instruction::LocalGet(digit));
+    self.instruction(Instruction::I64Sub);
+    self.instruction(Instruction::LocalGet(digit));
+    self.instruction(Instruction::LocalGet(negative));
+    self.instruction(Instruction::Select);
+    self.instruction(Instruction::I64Add);
+    self.instruction(Instruction::I64Store8(mem));
+    // value /= 10, keep going while there are digits left
+    self.instruction(Instruction::LocalGet(value));
+    self.instruction(Instruction::I64Const(10));
+    self.instruction(Instruction::I64DivS);
+    self.instruction(Instruction::LocalTee(value));
+    self.instruction(Instruction::I64Const(0));
+    self.instruction(Instruction::I64Ne);
+    self.instruction(Instruction::BrIf(0));
+    self.instruction(Instruction::End);
+
+    // Put the sign in front of negative numbers
+    self.instruction(Instruction::LocalGet(negative));
+    self.instruction(Instruction::If(BlockType::Empty));
+    self.instruction(Instruction::LocalGet(ptr));
+    self.instruction(Instruction::I32Const(1));
+    self.instruction(Instruction::I32Sub);
+    self.instruction(Instruction::LocalTee(ptr));
+    self.instruction(Instruction::I32Const('-' as i32));
+    self.instruction(Instruction::I32Store8(mem));
+    self.instruction(Instruction::End);
+
+    self.instruction(Instruction::LocalGet(ptr));
+    self.instruction(Instruction::End);
+
+    let mut local_names = NameMap::new();
+    local_names.append(value, "value");
+    local_names.append(end, "end");
+    local_names.append(ptr, "ptr");
+    local_names.append(digit, "digit");
+    local_names.append(negative, "negative");
+    self.name.local_names.append(idx, &local_names);
+    self.codes.function(&self.current_func.take().unwrap());
+  }
+
+  /// Generate `__str_concat(lhs, rhs, free_lhs, free_rhs) -> string` which
+  /// copies the text of both strings into a new allocation on the heap. The
+  /// strings that are flagged to be freed are freed once they are copied.
+  fn generate_str_concat(&mut self) {
+    let idx = self.fn_map[Builtin::StrConcat.name()];
+    self.functions.function(idx);
+
+    let (lhs, rhs, free_lhs, free_rhs, lhs_len, rhs_len, ptr) = (0, 1, 2, 3, 4, 5, 6);
+    self.current_func = Some(Function::new_with_locals_types(vec![ValType::I32; 3]));
+
+    self.instruction(Instruction::LocalGet(lhs));
+    self.str_len();
+    self.instruction(Instruction::LocalSet(lhs_len));
+    self.instruction(Instruction::LocalGet(rhs));
+    self.str_len();
+    self.instruction(Instruction::LocalSet(rhs_len));
+    let alloc = self.builtin(Builtin::Alloc);
+    self.instruction(Instruction::LocalGet(lhs_len));
+    self.instruction(Instruction::LocalGet(rhs_len));
+    self.instruction(Instruction::I32Add);
+    self.instruction(Instruction::Call(alloc));
+    self.instruction(Instruction::LocalSet(ptr));
+    // Copy the left side to the start and the right side after it
+    self.instruction(Instruction::LocalGet(ptr));
+    self.instruction(Instruction::LocalGet(lhs));
+    self.instruction(Instruction::I32WrapI64);
+    self.instruction(Instruction::LocalGet(lhs_len));
+    self.instruction(Instruction::MemoryCopy { src: 0, dst: 0 });
+    self.instruction(Instruction::LocalGet(ptr));
+    self.instruction(Instruction::LocalGet(lhs_len));
+    self.instruction(Instruction::I32Add);
+    self.instruction(Instruction::LocalGet(rhs));
+    self.instruction(Instruction::I32WrapI64);
+    self.instruction(Instruction::LocalGet(rhs_len));
+    self.instruction(Instruction::MemoryCopy { src: 0, dst: 0 });
+    self.free_if(free_lhs, lhs);
+    self.free_if(free_rhs, rhs);
+    // ptr | (lhs_len + rhs_len) << 32
+    self.instruction(Instruction::LocalGet(ptr));
+    self.instruction(Instruction::I64ExtendI32U);
+    self.instruction(Instruction::LocalGet(lhs_len));
+    self.instruction(Instruction::LocalGet(rhs_len));
+    self.instruction(Instruction::I32Add);
+    self.instruction(Instruction::I64ExtendI32U);
+    self.instruction(Instruction::I64Const(32));
+    self.instruction(Instruction::I64Shl);
+    self.instruction(Instruction::I64Or);
+    self.instruction(Instruction::End);
+
+    let mut local_names = NameMap::new();
+    local_names.append(lhs, "lhs");
+    local_names.append(rhs, "rhs");
+    local_names.append(free_lhs, "free_lhs");
+    local_names.append(free_rhs, "free_rhs");
+    local_names.append(lhs_len, "lhs_len");
+    local_names.append(rhs_len, "rhs_len");
+    local_names.append(ptr, "ptr");
+    self.name.local_names.append(idx, &local_names);
+    self.codes.function(&self.current_func.take().unwrap());
+  }
+
+  /// Generate `__str_eq(lhs, rhs, free_lhs, free_rhs) -> bool` which
+  /// compares the text of two strings a byte at a time. The strings that are
+  /// flagged to be freed are freed once they are compared.
+  fn generate_str_eq(&mut self) {
+    let idx = self.fn_map[Builtin::StrEq.name()];
+    self.functions.function(idx);
+
+    let (lhs, rhs, free_lhs, free_rhs, lhs_ptr, rhs_ptr, end, equal) = (0, 1, 2, 3, 4, 5, 6, 7);
+    self.current_func = Some(Function::new_with_locals_types(vec![ValType::I32; 4]));
+    let byte = mem_arg(0);
+
+    // The comparison breaks out of this block with the result
+    self.instruction(Instruction::Block(BlockType::Result(ValType::I32)));
+    // The same text in the same place is always equal
+    self.instruction(Instruction::LocalGet(lhs));
+    self.instruction(Instruction::LocalGet(rhs));
+    self.instruction(Instruction::I64Eq);
+    self.instruction(Instruction::If(BlockType::Empty));
+    self.instruction(Instruction::I32Const(1));
+    self.instruction(Instruction::Br(1));
+    self.instruction(Instruction::End);
+    // Strings of different lengths can't be equal
+    self.instruction(Instruction::LocalGet(lhs));
+    self.str_len();
+    self.instruction(Instruction::LocalGet(rhs));
+    self.str_len();
+    self.instruction(Instruction::I32Ne);
+    self.instruction(Instruction::If(BlockType::Empty));
+    self.instruction(Instruction::I32Const(0));
+    self.instruction(Instruction::Br(1));
+    self.instruction(Instruction::End);
+
+    self.instruction(Instruction::LocalGet(lhs));
+    self.instruction(Instruction::I32WrapI64);
+    self.instruction(Instruction::LocalTee(lhs_ptr));
+    self.instruction(Instruction::LocalGet(lhs));
+    self.str_len();
+    self.instruction(Instruction::I32Add);
+    self.instruction(Instruction::LocalSet(end));
+    self.instruction(Instruction::LocalGet(rhs));
+    self.instruction(Instruction::I32WrapI64);
+    self.instruction(Instruction::LocalSet(rhs_ptr));
+
+    self.instruction(Instruction::Block(BlockType::Empty));
+    self.instruction(Instruction::Loop(BlockType::Empty));
+    self.instruction(Instruction::LocalGet(lhs_ptr));
+    self.instruction(Instruction::LocalGet(end));
+    self.instruction(Instruction::I32Eq);
+    self.instruction(Instruction::BrIf(1));
+    self.instruction(Instruction::LocalGet(lhs_ptr));
+    self.instruction(Instruction::I32Load8_U(byte));
+    self.instruction(Instruction::LocalGet(rhs_ptr));
+    self.instruction(Instruction::I32Load8_U(byte));
+    self.instruction(Instruction::I32Ne);
+    self.instruction(Instruction::If(BlockType::Empty));
+    self.instruction(Instruction::I32Const(0));
+    self.instruction(Instruction::Br(3));
+    self.instruction(Instruction::End);
+    self.instruction(Instruction::LocalGet(lhs_ptr));
+    self.instruction(Instruction::I32Const(1));
+    self.instruction(Instruction::I32Add);
+    self.instruction(Instruction::LocalSet(lhs_ptr));
+    self.instruction(Instruction::LocalGet(rhs_ptr));
+    self.instruction(Instruction::I32Const(1));
+    self.instruction(Instruction::I32Add);
+    self.instruction(Instruction::LocalSet(rhs_ptr));
+    self.instruction(Instruction::Br(0));
+    self.instruction(Instruction::End);
+    self.instruction(Instruction::End);
+    self.instruction(Instruction::I32Const(1));
+    self.instruction(Instruction::End);
+    self.instruction(Instruction::LocalSet(equal));
+
+    self.free_if(free_lhs, lhs);
+    self.free_if(free_rhs, rhs);
+    self.instruction(Instruction::LocalGet(equal));
+    self.instruction(Instruction::End);
+
+    let mut local_names = NameMap::new();
+    local_names.append(lhs, "lhs");
+    local_names.append(rhs, "rhs");
+    local_names.append(free_lhs, "free_lhs");
+    local_names.append(free_rhs, "free_rhs");
+    local_names.append(lhs_ptr, "lhs_ptr");
+    local_names.append(rhs_ptr, "rhs_ptr");
+    local_names.append(end, "end");
+    local_names.append(equal, "equal");
+    self.name.local_names.append(idx, &local_names);
+    self.codes.function(&self.current_func.take().unwrap());
+  }
+
+  /// Create instructions to free the string in the local `string` if the
+  /// local `flag` is set
+  fn free_if(&mut self, flag: u32, string: u32) {
+    let free = self.builtin(Builtin::Free);
+    self.instruction(Instruction::LocalGet(flag));
+    self.instruction(Instruction::If(BlockType::Empty));
+    self.instruction(Instruction::LocalGet(string));
+    self.instruction(Instruction::I32WrapI64);
+    self.instruction(Instruction::Call(free));
+    self.instruction(Instruction::End);
+  }
+
+  /// Create instructions to get the length of the string on top of the stack
+  fn str_len(&mut self) {
+    self.instruction(Instruction::I64Const(32));
+    self.instruction(Instruction::I64ShrU);
+    self.instruction(Instruction::I32WrapI64);
+  }
+}
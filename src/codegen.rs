@@ -1,10 +1,22 @@
-use crate::{context::SycContext, types::Statement, wasi::WasiFnHelpers};
+use crate::{
+  builtins::{Builtin, Builtins},
+  compiler::Target,
+  context::SycContext,
+  diagnostics::Diagnostic,
+  doc::{StateDoc, DOCS_SECTION},
+  emit::wasm_to_wat,
+  error::ErrorCode,
+  memory::{Memory, PAGE_SIZE, STACK_SIZE},
+  types::{Ident, Param, Statement, Type},
+  validate::{validate, InvalidModule},
+  wasi::WasiFnHelpers,
+};
 use std::collections::HashMap;
 use wasm_encoder::*;
 
-/// Largest idx in mem where each increase by 1 is 8 bytes
-pub const MAX_MEM: i32 = 65536;
-pub const RESULT_IDX: i32 = MAX_MEM - 4;
+/// Every state function returns the function index of the state to transition
+/// to next. A state that finishes without transitioning returns this instead.
+pub const STATE_RETURN: i32 = -1;
 
 /// `Codegen` is the main driver in sycamore that wraps various sections of a
 /// wasm file with various fields to keep track of things. The `Generate` trait
@@ -14,6 +26,8 @@ pub const RESULT_IDX: i32 = MAX_MEM - 4;
 pub struct Codegen {
   /// Boolean to determine if we print out debug info when generating code
   pub debug: bool,
+  /// The WASI version the program imports its host functions from
+  pub target: Target,
   /// List of all `Statements` that the code will be generated from
   pub stmt: Vec<Statement>,
   /// The `Module` that will be output at the end of code generation
@@ -24,6 +38,10 @@ pub struct Codegen {
   pub data: DataSection,
   /// WebAssembly Memory Section
   pub memory: MemorySection,
+  /// WebAssembly Global Section
+  pub globals: GlobalSection,
+  /// Map of Global Name to Global Number in the binary file
+  pub global_map: HashMap<String, u32>,
   /// All of the names of various items for the WebAssembly Name Section
   pub name: Name,
   /// WebAssembly Type Section
@@ -34,19 +52,41 @@ pub struct Codegen {
   pub exports: ExportSection,
   /// WebAssembly Code Section
   pub codes: CodeSection,
-  /// Table of String Literals that we use while generating code
-  pub literal_table: Vec<String>,
+  /// Every string literal written into memory and the offset it starts at
+  pub literals: HashMap<String, i32>,
+  /// The bytes of every string literal laid out one after another. They get
+  /// written into memory by a single data segment right after the stack.
+  pub static_data: Vec<u8>,
   /// Map of Function Name to Function Number in the binary file
   pub fn_map: HashMap<String, u32>,
+  /// Every builtin the program uses in the order they were added
+  pub builtins: Vec<Builtin>,
+  /// Map of State Name to the globals holding the arguments for the state.
+  /// A `goto` can't pass arguments on the stack since the state is called by
+  /// the dispatcher, so each parameter gets a global to hold its argument.
+  pub state_globals: HashMap<String, Vec<u32>>,
   /// The current function we are operating on
   pub current_func: Option<Function>,
+  /// Map of variable name to local in the current function
+  pub locals: HashMap<String, Local>,
+  /// Local in the current function used to hold the state a called state
+  /// transitioned to
+  pub next_state_local: u32,
   pub ctx: SycContext,
 }
 
+/// A local variable in the function code is being generated for
+#[derive(Debug, Clone, Copy)]
+pub struct Local {
+  pub idx: u32,
+  pub ty: Type,
+}
+
 /// A struct of all names for the WebAssembly Name Section
 pub struct Name {
   pub function_names: NameMap,
   pub memory_names: NameMap,
+  pub global_names: NameMap,
   pub type_names: NameMap,
   pub local_names: IndirectNameMap,
 }
@@ -57,6 +97,7 @@ impl Name {
     Self {
       function_names: NameMap::new(),
       memory_names: NameMap::new(),
+      global_names: NameMap::new(),
       type_names: NameMap::new(),
       local_names: IndirectNameMap::new(),
     }
@@ -71,28 +112,37 @@ impl Name {
     section.locals(&self.local_names);
     section.types(&self.type_names);
     section.memories(&self.memory_names);
+    section.globals(&self.global_names);
     section
   }
 }
 
 impl Codegen {
-  /// Create a new `Codegen`
-  pub fn new(stmt: Vec<Statement>, debug: bool) -> Self {
+  /// Create a new `Codegen` for the given WASI target
+  pub fn new(stmt: Vec<Statement>, debug: bool, target: Target) -> Self {
     Self {
       debug,
+      target,
       stmt,
       main_mod: Module::new(),
       imports: ImportSection::new(),
       data: DataSection::new(),
       memory: MemorySection::new(),
+      globals: GlobalSection::new(),
+      global_map: HashMap::new(),
       name: Name::new(),
       types: TypeSection::new(),
       functions: FunctionSection::new(),
       exports: ExportSection::new(),
       codes: CodeSection::new(),
-      literal_table: Vec::new(),
+      literals: HashMap::new(),
+      static_data: Vec::new(),
       fn_map: HashMap::new(),
+      builtins: Vec::new(),
+      state_globals: HashMap::new(),
       current_func: None,
+      locals: HashMap::new(),
+      next_state_local: 0,
       ctx: SycContext::new(),
     }
   }
@@ -110,74 +160,242 @@ impl Codegen {
       .instruction(&instruction);
   }
 
-  /// Turn all of the code into a wasm binary
-  fn finish(self) -> Vec<u8> {
-    self.main_mod.finish().to_vec()
+  /// Register a function defined in this module under the given name. Every
+  /// function gets its own entry in the type section at the same index as the
+  /// function itself, so the returned index is both.
+  pub fn register_function(
+    &mut self,
+    name: &str,
+    params: Vec<ValType>,
+    results: Vec<ValType>,
+  ) -> u32 {
+    self.types.function(params, results);
+    let idx = self.fn_map.len() as u32;
+    self.name.function_names.append(idx, name);
+    self.name.type_names.append(idx, name);
+    self.fn_map.insert(name.into(), idx);
+    idx
   }
 
-  /// Generate the code for the given statements and consume the `Codegen` in the
-  /// process.
-  pub fn generate(mut self) -> Vec<u8> {
-    self.wasi_imports();
+  /// Add a mutable global with the given name and initial value, returning
+  /// its index
+  pub fn add_global(&mut self, name: &str, val_type: ValType, init: &Instruction) -> u32 {
+    self.globals.global(
+      GlobalType {
+        val_type,
+        mutable: true,
+      },
+      init,
+    );
+    let idx = self.global_map.len() as u32;
+    self.name.global_names.append(idx, name);
+    self.global_map.insert(name.into(), idx);
+    idx
+  }
+
+  /// Register a state and the globals used to pass arguments to it, returning
+  /// the function index of the state
+  fn register_state(&mut self, name: &Ident, params: &[Param]) -> u32 {
+    // States return the next state to go to
+    let idx = self.register_function(
+      name.as_str(),
+      params.iter().map(|p| p.ty.val_type()).collect(),
+      vec![ValType::I32],
+    );
+    let globals = params
+      .iter()
+      .map(|p| {
+        let global = format!("{}.{}", name.as_str(), p.name.as_str());
+        self.add_global(&global, p.ty.val_type(), &p.ty.zero())
+      })
+      .collect();
+    self.state_globals.insert(name.as_str().into(), globals);
+    idx
+  }
+
+  /// Write a string literal into memory, returning the offset it starts at.
+  /// The same literal is only ever written once.
+  pub fn add_literal(&mut self, literal: &str) -> i32 {
+    if let Some(offset) = self.literals.get(literal) {
+      return *offset;
+    }
+    let offset = STACK_SIZE + self.static_data.len() as i32;
+    self.static_data.extend_from_slice(literal.as_bytes());
+    self.literals.insert(literal.into(), offset);
+    offset
+  }
+
+  /// Write all of the static data into the data section as one segment and
+  /// make memory start out big enough to hold it along with the stack
+  fn generate_static_data(&mut self) {
+    if !self.static_data.is_empty() {
+      self.data.active(
+        0,
+        &Instruction::I32Const(STACK_SIZE),
+        self.static_data.iter().copied(),
+      );
+    }
+    let pages = (self.heap_start() + PAGE_SIZE - 1) / PAGE_SIZE;
     self.memory.memory(MemoryType {
-      minimum: 1,
+      minimum: pages as u64,
       maximum: None,
       memory64: false,
     });
+  }
+
+  /// Get the function index of the state with the given name
+  pub fn state_index(&self, name: &Ident) -> Result<u32, Diagnostic> {
+    match self.fn_map.get(name.as_str()) {
+      Some(idx) => Ok(*idx),
+      None => Err(
+        Diagnostic::error(
+          ErrorCode::UnknownState,
+          format!("no state named `{}`", name.as_str()),
+          name.span(),
+        )
+        .with_label(name.span(), "this state is not defined"),
+      ),
+    }
+  }
+
+  /// Generate the `_start` function which drives the state machine. It starts
+  /// in `main` and keeps calling whichever state the last one transitioned to
+  /// until a state returns without transitioning. Doing this in a loop rather
+  /// than having states call each other means a program can transition
+  /// forever without growing the stack.
+  /// States that take parameters are given the arguments left in their
+  /// globals by whatever transitioned to them.
+  fn generate_dispatcher(&mut self, states: &[(u32, Vec<u32>)]) -> Result<(), Diagnostic> {
+    let main = match self.fn_map.get("main") {
+      Some(main) => *main,
+      None => {
+        return Err(Diagnostic::error(
+          ErrorCode::InternalError,
+          "no `main` state was registered",
+          0..0,
+        ))
+      }
+    };
+    let start = self.register_function("_start", Vec::new(), Vec::new());
+    self.functions.function(start);
+    self.exports.export("_start", Export::Function(start));
+
+    // The only local is the state that should run next
+    let current = 0;
+    self.current_func = Some(Function::new_with_locals_types(vec![ValType::I32]));
+    self.init_memory();
+    self.instruction(Instruction::I32Const(main as i32));
+    self.instruction(Instruction::LocalSet(current));
+    self.instruction(Instruction::Loop(BlockType::Empty));
+    for (state, globals) in states {
+      self.instruction(Instruction::LocalGet(current));
+      self.instruction(Instruction::I32Const(*state as i32));
+      self.instruction(Instruction::I32Eq);
+      self.instruction(Instruction::If(BlockType::Empty));
+      for global in globals {
+        self.instruction(Instruction::GlobalGet(*global));
+      }
+      self.instruction(Instruction::Call(*state));
+      self.instruction(Instruction::LocalSet(current));
+      self.instruction(Instruction::End);
+    }
+    // Keep going as long as the last state transitioned somewhere
+    self.instruction(Instruction::LocalGet(current));
+    self.instruction(Instruction::I32Const(0));
+    self.instruction(Instruction::I32GeS);
+    self.instruction(Instruction::BrIf(0));
+    self.instruction(Instruction::End);
+    self.instruction(Instruction::End);
 
+    let mut local_names = NameMap::new();
+    local_names.append(current, "current-state");
+    self.name.local_names.append(start, &local_names);
+    self.codes.function(&self.current_func.take().unwrap());
+    Ok(())
+  }
+
+  /// Turn all of the code into a wasm binary
+  fn finish(self) -> Vec<u8> {
+    self.main_mod.finish().to_vec()
+  }
+
+  /// Generate the code for the given statements and consume the `Codegen` in the
+  /// process. Returns a `Diagnostic` if the statements are not valid or the
+  /// generated module fails validation.
+  pub fn generate(mut self) -> Result<Vec<u8>, Diagnostic> {
+    self.wasi_imports();
     self.name.memory_names.append(0, "memory");
     self.exports.export("memory", Export::Memory(0));
 
-    // Setup the new line for printing with a newline
-    self.literal_table.push("\n".into());
-    self.data.active(0, &Instruction::I32Const(0), ['\n' as u8]);
-
     // Setup the function map and types after our import so that we can make
-    // calls to them properly everywhere. Also create all of our string literals
-    // before hand.
-    for stmt in self.stmt.iter() {
-      if let Statement::StateDefn { name, .. } = stmt {
-        // All states are the void type for now until we deal with args
-        self.types.function(Vec::new(), Vec::new());
-        let name = name.as_str().to_string();
-        let idx = self.fn_map.len() as u32;
-        self.name.function_names.append(idx, &name);
-        self.name.type_names.append(idx, &name);
-        self.fn_map.insert(name, idx);
+    // calls to them properly everywhere.
+    let mut states = Vec::new();
+    for stmt in self.stmt.clone().iter() {
+      if let Statement::StateDefn { name, input, .. } = stmt {
+        let idx = self.register_state(name, input);
+        let globals = self.state_globals[name.as_str()].clone();
+        states.push((idx, globals));
       }
     }
+    self.register_memory();
 
     for statement in self.stmt.clone().iter() {
-      statement.generate(&mut self);
+      statement.generate(&mut self)?;
     }
+    self.generate_builtins();
+    self.generate_dispatcher(&states)?;
+    self.generate_static_data();
 
     // Set the sections in the right order
     self.main_mod.section(&self.types);
     self.main_mod.section(&self.imports);
     self.main_mod.section(&self.functions);
     self.main_mod.section(&self.memory);
+    self.main_mod.section(&self.globals);
     self.main_mod.section(&self.exports);
     self.main_mod.section(&self.codes);
     self.main_mod.section(&self.data);
     self.main_mod.section(&self.name.make_section());
+    let ctx = bincode::serialize(&self.ctx).map_err(|e| {
+      Diagnostic::error(
+        ErrorCode::InternalError,
+        format!("failed to encode the SycContext: {}", e),
+        0..0,
+      )
+    })?;
     self.main_mod.section(&CustomSection {
       name: "SycContext",
-      data: &bincode::serialize(&self.ctx).unwrap(),
+      data: &ctx,
+    });
+    let docs = bincode::serialize(&StateDoc::collect(&self.stmt)).map_err(|e| {
+      Diagnostic::error(
+        ErrorCode::InternalError,
+        format!("failed to encode the state docs: {}", e),
+        0..0,
+      )
+    })?;
+    self.main_mod.section(&CustomSection {
+      name: DOCS_SECTION,
+      data: &docs,
     });
     // Create and validate
     let debug = self.debug;
+    let program = std::mem::take(&mut self.stmt);
     let wasm = self.finish();
+    validate(&wasm).map_err(|invalid| invalid_module(&program, invalid))?;
     if debug {
       println!("---------------- Codegen WAT Output ----------------");
-      let wat = wabt::Wasm2Wat::new()
-        .read_debug_names(true)
-        .convert(&wasm)
-        .map(|buf| String::from_utf8(buf.as_ref().to_vec()).unwrap())
-        .unwrap();
+      let wat = wasm_to_wat(&wasm).map_err(|e| {
+        Diagnostic::error(
+          ErrorCode::InternalError,
+          format!("failed to convert the generated module to WAT: {}", e),
+          0..0,
+        )
+      })?;
       println!("{}", wat);
     }
 
-    wasm
+    Ok(wasm)
   }
 }
 
@@ -185,5 +403,93 @@ impl Codegen {
 /// a type and pass a `Codegen` type into it. This then gets called to generate
 /// code for everything.
 pub trait Generate {
-  fn generate(&self, codegen: &mut Codegen);
+  fn generate(&self, codegen: &mut Codegen) -> Result<(), Diagnostic>;
+}
+
+/// Create the error for a generated module that is not valid, pointing at the
+/// state it was generated from when there is one
+fn invalid_module(program: &[Statement], invalid: InvalidModule) -> Diagnostic {
+  let state = invalid.function.as_deref().and_then(|function| {
+    program.iter().find_map(|stmt| match stmt {
+      Statement::StateDefn { name, .. } if name.as_str() == function => Some(name),
+      _ => None,
+    })
+  });
+  let mut diagnostic = Diagnostic::error(
+    ErrorCode::InternalError,
+    format!(
+      "the compiler generated an invalid module: {}",
+      invalid.message
+    ),
+    state.map_or(0..0, |name| name.span()),
+  );
+  if let Some(name) = state {
+    diagnostic = diagnostic.with_label(
+      name.span(),
+      format!("the code generated for `{}` is invalid", name.as_str()),
+    );
+  }
+  let location = match &invalid.function {
+    Some(function) => format!("in function `{}`", function),
+    None => "outside of any function".to_string(),
+  };
+  diagnostic
+    .with_note(format!(
+      "the problem is at byte {:#x} {}",
+      invalid.offset, location
+    ))
+    .with_note("this is a bug in the compiler, please report it along with this program")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::parser::SycParser;
+
+  /// A module with a function named `main` that adds two numbers it never
+  /// pushed onto the stack
+  fn corrupted_module() -> Vec<u8> {
+    let mut types = TypeSection::new();
+    types.function(Vec::<ValType>::new(), Vec::<ValType>::new());
+    let mut functions = FunctionSection::new();
+    functions.function(0);
+    let mut codes = CodeSection::new();
+    let mut body = Function::new_with_locals_types(Vec::new());
+    body.instruction(&Instruction::I32Add);
+    body.instruction(&Instruction::End);
+    codes.function(&body);
+    let mut function_names = NameMap::new();
+    function_names.append(0, "main");
+    let mut names = NameSection::new();
+    names.functions(&function_names);
+
+    let mut module = Module::new();
+    module.section(&types);
+    module.section(&functions);
+    module.section(&codes);
+    module.section(&names);
+    module.finish().to_vec()
+  }
+
+  #[test]
+  fn invalid_module_points_at_the_state_it_came_from() {
+    let source = "terminating state main() {}";
+    let (program, errors) = SycParser::new(source).parse();
+    assert!(errors.is_empty(), "{:?}", errors);
+    let invalid = validate(&corrupted_module()).unwrap_err();
+    assert_eq!(invalid.function.as_deref(), Some("main"));
+
+    let diagnostic = invalid_module(&program, invalid);
+    assert_eq!(diagnostic.code, ErrorCode::InternalError);
+    assert!(
+      diagnostic
+        .message
+        .starts_with("the compiler generated an invalid module"),
+      "{}",
+      diagnostic.message
+    );
+    let main = source.find("main").unwrap();
+    assert_eq!(diagnostic.span, main..main + 4);
+    assert!(diagnostic.notes[0].ends_with("in function `main`"));
+  }
 }
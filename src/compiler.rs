@@ -0,0 +1,202 @@
+use crate::{
+  codegen::Codegen,
+  diagnostics::{Diagnostic, Diagnostics},
+  emit::{self, EmitKind},
+  error::{ErrorCode, SycError},
+  graph::StateGraph,
+  parser::SycParser,
+  resolve::Resolver,
+  sandbox::SandboxPolicy,
+  typeck::TypeChecker,
+  types::Statement,
+};
+use std::{fmt, str::FromStr};
+
+/// The WASI version compiled programs import their host functions from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+  /// The original WASI snapshot, `wasi_unstable`
+  WasiUnstable,
+  /// WASI preview 1, `wasi_snapshot_preview1`
+  WasiPreview1,
+}
+
+impl Target {
+  /// The name of the module the WASI functions are imported from
+  pub fn wasi_module(&self) -> &'static str {
+    match self {
+      Target::WasiUnstable => "wasi_unstable",
+      Target::WasiPreview1 => "wasi_snapshot_preview1",
+    }
+  }
+}
+
+impl Default for Target {
+  fn default() -> Self {
+    Target::WasiUnstable
+  }
+}
+
+impl FromStr for Target {
+  type Err = String;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "wasi_unstable" => Ok(Target::WasiUnstable),
+      "wasi_snapshot_preview1" => Ok(Target::WasiPreview1),
+      _ => Err(format!(
+        "unknown target `{}`, expected wasi_unstable or wasi_snapshot_preview1",
+        s
+      )),
+    }
+  }
+}
+
+impl fmt::Display for Target {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.wasi_module())
+  }
+}
+
+/// How a `Compiler` compiles programs
+#[derive(Debug, Clone, Default)]
+pub struct CompileOptions {
+  /// Print the source and generated WAT while compiling
+  pub debug: bool,
+  /// The extra kinds of output to produce alongside the module, see
+  /// [`CompiledModule::output`]
+  pub emit: Vec<EmitKind>,
+  /// The WASI version to compile for
+  pub target: Target,
+  /// The sandbox the program runs in unless the host gives it another one.
+  /// Its directories and environment variables are left out when the
+  /// program runs, only the host can grant those.
+  pub sandbox: SandboxPolicy,
+}
+
+/// A sycamore program compiled by a `Compiler`
+#[derive(Debug, Clone)]
+pub struct CompiledModule {
+  /// The name the program was compiled under
+  pub name: String,
+  /// The compiled module
+  pub wasm: Vec<u8>,
+  /// The syntax tree of the program with the types of its variables filled in
+  pub ast: Vec<Statement>,
+  /// Warnings found in the program
+  pub warnings: Diagnostics,
+  outputs: Vec<(EmitKind, Vec<u8>)>,
+}
+
+impl CompiledModule {
+  /// Get the module as WebAssembly text
+  pub fn wat(&self) -> Result<String, SycError> {
+    emit::wasm_to_wat(&self.wasm).map_err(|e| SycError::Wat(e.to_string()))
+  }
+
+  /// Get one of the kinds of output that were asked for in the
+  /// `CompileOptions`
+  pub fn output(&self, kind: EmitKind) -> Option<&[u8]> {
+    self
+      .outputs
+      .iter()
+      .find(|(emitted, _)| *emitted == kind)
+      .map(|(_, output)| output.as_slice())
+  }
+}
+
+/// Compiles sycamore programs from source held in memory, without reading or
+/// writing any files or printing any problems it finds
+#[derive(Debug, Clone, Default)]
+pub struct Compiler {
+  pub options: CompileOptions,
+}
+
+impl Compiler {
+  /// Create a `Compiler` with the given options
+  pub fn new(options: CompileOptions) -> Self {
+    Self { options }
+  }
+
+  /// Compile the source of a program. `name` labels the problems found in it,
+  /// usually it is the name of the file the source came from.
+  pub fn compile_str(&self, source: &str, name: &str) -> Result<CompiledModule, Diagnostics> {
+    let (parsed, mut diagnostics) = analyze(source);
+    let into_diagnostics = |list| Diagnostics {
+      name: name.to_string(),
+      source: source.to_string(),
+      list,
+    };
+    if diagnostics.iter().any(Diagnostic::is_error) {
+      return Err(into_diagnostics(diagnostics));
+    }
+    if self.options.debug {
+      println!("------------------ Sycamore Input ------------------");
+      println!("{}", source);
+    }
+    let mut codegen = Codegen::new(parsed.clone(), self.options.debug, self.options.target);
+    codegen.ctx.sandbox = self.options.sandbox.clone();
+    let wasm = match codegen.generate() {
+      Ok(wasm) => wasm,
+      Err(e) => {
+        diagnostics.push(e);
+        return Err(into_diagnostics(diagnostics));
+      }
+    };
+    let mut module = CompiledModule {
+      name: name.to_string(),
+      wasm,
+      ast: parsed,
+      warnings: into_diagnostics(diagnostics),
+      outputs: Vec::new(),
+    };
+    for kind in &self.options.emit {
+      let output = match kind {
+        EmitKind::Tokens => emit::tokens(source).into_bytes(),
+        EmitKind::Ast => format!("{:#?}\n", module.ast).into_bytes(),
+        EmitKind::Wat => match module.wat() {
+          Ok(wat) => wat.into_bytes(),
+          Err(e) => {
+            let diagnostic = Diagnostic::error(
+              ErrorCode::InternalError,
+              format!("failed to convert the generated module to WAT: {}", e),
+              0..0,
+            );
+            return Err(into_diagnostics(vec![diagnostic]));
+          }
+        },
+        EmitKind::Wasm => module.wasm.clone(),
+      };
+      module.outputs.push((*kind, output));
+    }
+    Ok(module)
+  }
+
+  /// Parse and check the source of a program without generating any code,
+  /// returning its syntax tree along with every problem found in it
+  pub fn check_str(&self, source: &str, name: &str) -> (Vec<Statement>, Diagnostics) {
+    let (parsed, list) = analyze(source);
+    let diagnostics = Diagnostics {
+      name: name.to_string(),
+      source: source.to_string(),
+      list,
+    };
+    (parsed, diagnostics)
+  }
+}
+
+/// Parse and check a program, returning its syntax tree with the types of its
+/// variables filled in along with every problem found in it
+fn analyze(input: &str) -> (Vec<Statement>, Vec<Diagnostic>) {
+  let (mut parsed, mut diagnostics) = SycParser::new(input).parse();
+  // Only check the meaning of programs that parsed cleanly, a partial AST
+  // would lead to a lot of confusing errors. The same goes for programs with
+  // names that don't resolve.
+  if !diagnostics.iter().any(Diagnostic::is_error) {
+    diagnostics.extend(Resolver::new(&parsed).resolve(&parsed));
+  }
+  if !diagnostics.iter().any(Diagnostic::is_error) {
+    diagnostics.extend(StateGraph::new(&parsed).check());
+    diagnostics.extend(TypeChecker::new(&parsed).check(&mut parsed));
+  }
+  (parsed, diagnostics)
+}
@@ -1,34 +1,55 @@
+use crate::{error::SycError, sandbox::SandboxPolicy};
 use serde::{Deserialize, Serialize};
 use wasmparser::{Parser, Payload};
+use wasmtime::StoreLimits;
 use wasmtime_wasi::WasiCtx;
 
 /// `SycContext` is what hosts all of the needed context to run a sycamore
-/// program. As of right now this is just `WasiCtx` which gets added in at
-/// runtime, but more might be added in the future. It gets encoded into the
-/// custom section of the wasm binary and can be retrieved from it to run the
-/// program.
+/// program. This is the sandbox policy the program was compiled with, along
+/// with the `WasiCtx` and store limits which get added in at runtime. It gets
+/// encoded into the custom section of the wasm binary and can be retrieved
+/// from it to run the program.
 #[derive(Serialize, Deserialize)]
 pub struct SycContext {
+  pub sandbox: SandboxPolicy,
   #[serde(skip)]
   pub wasi: Option<WasiCtx>,
+  #[serde(skip)]
+  pub limits: StoreLimits,
 }
 
 impl SycContext {
   /// Create a new `SycContext`
   pub fn new() -> Self {
-    Self { wasi: None }
+    Self {
+      sandbox: SandboxPolicy::default(),
+      wasi: None,
+      limits: StoreLimits::default(),
+    }
+  }
+  /// Decode a `SycContext` from the contents of its custom section
+  pub fn from_section(data: &[u8]) -> Result<Self, SycError> {
+    bincode::deserialize(data).map_err(|e| SycError::Link(e))
   }
-  /// Retrieve a `SycContext` from a sycamore binary.
-  pub fn from_sycamore_binary(wasm: &[u8]) -> Self {
-    bincode::deserialize(
-      Parser::new(0)
-        .parse_all(&wasm)
-        .find_map(|payload| match payload.unwrap() {
-          Payload::CustomSection { data, .. } => Some(data),
-          _ => None,
-        })
-        .unwrap(),
-    )
-    .unwrap()
+
+  /// Find the contents of the `SycContext` custom section of a sycamore
+  /// binary. This fails if the binary is not a valid wasm module or was not
+  /// compiled by sycamore.
+  pub fn section(wasm: &[u8]) -> Result<&[u8], SycError> {
+    let mut data = None;
+    for payload in Parser::new(0).parse_all(wasm) {
+      if let Payload::CustomSection {
+        name: "SycContext",
+        data: section,
+        ..
+      } = payload.map_err(|e| SycError::Link(e.into()))?
+      {
+        data = Some(section);
+        break;
+      }
+    }
+    data.ok_or_else(|| {
+      SycError::Link("the module has no SycContext section, was it compiled by sycamore?".into())
+    })
   }
 }
@@ -0,0 +1,254 @@
+use crate::error::ErrorCode;
+use ariadne::{Color, Label, Report, ReportKind, Source};
+use std::{error::Error, fmt, io, ops::Range};
+
+/// How severe a `Diagnostic` is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+  Error,
+  Warning,
+}
+
+/// A message pointing at a specific span of the source code
+#[derive(Debug, Clone)]
+pub struct SpanLabel {
+  pub span: Range<usize>,
+  pub message: String,
+}
+
+/// A `Diagnostic` is a problem found in a sycamore program while compiling
+/// it. It carries the span of the source code the problem is in so that it
+/// can be rendered as a report pointing at the offending code.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+  /// How severe the problem is
+  pub level: Level,
+  /// The code identifying the kind of problem
+  pub code: ErrorCode,
+  /// The main message describing the problem
+  pub message: String,
+  /// Where in the source the problem is
+  pub span: Range<usize>,
+  /// Extra messages pointing at parts of the source. If this is empty the
+  /// `message` is used to label the `span`
+  pub labels: Vec<SpanLabel>,
+  /// Notes and help messages shown below the source
+  pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+  /// Create a new error `Diagnostic`
+  pub fn error(code: ErrorCode, message: impl ToString, span: Range<usize>) -> Self {
+    Self {
+      level: Level::Error,
+      code,
+      message: message.to_string(),
+      span,
+      labels: Vec::new(),
+      notes: Vec::new(),
+    }
+  }
+
+  /// Create a new warning `Diagnostic`
+  pub fn warning(code: ErrorCode, message: impl ToString, span: Range<usize>) -> Self {
+    Self {
+      level: Level::Warning,
+      ..Self::error(code, message, span)
+    }
+  }
+
+  /// Add a label pointing at part of the source code
+  pub fn with_label(mut self, span: Range<usize>, message: impl ToString) -> Self {
+    self.labels.push(SpanLabel {
+      span,
+      message: message.to_string(),
+    });
+    self
+  }
+
+  /// Add a note to be shown below the source code
+  pub fn with_note(mut self, note: impl ToString) -> Self {
+    self.notes.push(note.to_string());
+    self
+  }
+
+  /// Is this `Diagnostic` an error
+  pub fn is_error(&self) -> bool {
+    self.level == Level::Error
+  }
+
+  /// Build the `ariadne` report for this `Diagnostic`
+  fn build_report(&self, file_name: &str) -> Report<(String, Range<usize>)> {
+    let kind = match self.level {
+      Level::Error => ReportKind::Error,
+      Level::Warning => ReportKind::Warning,
+    };
+    let color = match self.level {
+      Level::Error => Color::Red,
+      Level::Warning => Color::Yellow,
+    };
+    let mut report = Report::build(kind, file_name.to_string(), self.span.start)
+      .with_code(self.code)
+      .with_message(&self.message);
+
+    if self.labels.is_empty() {
+      report = report.with_label(
+        Label::new((file_name.to_string(), self.span.clone()))
+          .with_message(&self.message)
+          .with_color(color),
+      );
+    }
+    for (idx, label) in self.labels.iter().enumerate() {
+      // The first label is the primary one, the rest add context
+      let color = if idx == 0 { color } else { Color::Cyan };
+      report = report.with_label(
+        Label::new((file_name.to_string(), label.span.clone()))
+          .with_message(&label.message)
+          .with_color(color),
+      );
+    }
+    for note in &self.notes {
+      report = report.with_note(note);
+    }
+
+    report.finish()
+  }
+
+  /// Render this `Diagnostic` for the given source file to the given writer
+  pub fn write(&self, file_name: &str, source: &str, w: impl io::Write) -> io::Result<()> {
+    self
+      .build_report(file_name)
+      .write((file_name.to_string(), Source::from(source)), w)
+  }
+
+  /// Render this `Diagnostic` for the given source file to stderr
+  pub fn report(&self, file_name: &str, source: &str) -> io::Result<()> {
+    self.write(file_name, source, io::stderr())
+  }
+}
+
+impl fmt::Display for Diagnostic {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.message)
+  }
+}
+
+impl Error for Diagnostic {}
+
+/// Every problem found in a program along with the source they point into,
+/// so that they can be rendered later
+#[derive(Debug, Clone)]
+pub struct Diagnostics {
+  /// The name of the program, usually the file it came from
+  pub name: String,
+  /// The source of the program
+  pub source: String,
+  /// The problems found in the program in the order they were found
+  pub list: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+  /// Iterate over the problems
+  pub fn iter(&self) -> std::slice::Iter<'_, Diagnostic> {
+    self.list.iter()
+  }
+
+  /// Are there no problems at all
+  pub fn is_empty(&self) -> bool {
+    self.list.is_empty()
+  }
+
+  /// Are any of the problems errors
+  pub fn has_errors(&self) -> bool {
+    self.list.iter().any(Diagnostic::is_error)
+  }
+
+  /// Render every problem to the given writer
+  pub fn write(&self, mut w: impl io::Write) -> io::Result<()> {
+    for diagnostic in &self.list {
+      diagnostic.write(&self.name, &self.source, &mut w)?;
+    }
+    Ok(())
+  }
+
+  /// Render every problem to stderr
+  pub fn report(&self) -> io::Result<()> {
+    self.write(io::stderr())
+  }
+}
+
+impl fmt::Display for Diagnostics {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self.list.iter().filter(|d| d.is_error()).count() {
+      0 => write!(f, "`{}` compiled without errors", self.name),
+      1 => write!(f, "could not compile `{}` due to an error", self.name),
+      n => write!(f, "could not compile `{}` due to {} errors", self.name, n),
+    }
+  }
+}
+
+impl Error for Diagnostics {}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Render a diagnostic for `test.sm` without any colors
+  fn render(diagnostic: &Diagnostic, source: &str) -> String {
+    let mut out = Vec::new();
+    diagnostic.write("test.sm", source, &mut out).unwrap();
+    let rendered = String::from_utf8(out).unwrap();
+    let mut plain = String::new();
+    let mut chars = rendered.chars();
+    while let Some(c) = chars.next() {
+      if c == '\x1b' {
+        // Skip the rest of the color code
+        chars.by_ref().find(|&c| c == 'm');
+      } else {
+        plain.push(c);
+      }
+    }
+    plain
+  }
+
+  #[test]
+  fn reports_point_at_the_source() {
+    let source = "terminating state main() {\n  x <- 1 + true;\n}\n";
+    let start = source.find("true").unwrap();
+    let diagnostic = Diagnostic::error(
+      ErrorCode::TypeMismatch,
+      "Mismatched types",
+      start..start + 4,
+    )
+    .with_label(start..start + 4, "expected i32, found bool")
+    .with_note("help: compare with `equals` instead");
+    let report = render(&diagnostic, source);
+    for expected in [
+      "[E0210] Error: Mismatched types",
+      "test.sm:2:",
+      "x <- 1 + true;",
+      "expected i32, found bool",
+      "help: compare with `equals` instead",
+    ] {
+      assert!(
+        report.contains(expected),
+        "`{}` is not in\n{}",
+        expected,
+        report
+      );
+    }
+  }
+
+  #[test]
+  fn warnings_are_reported_as_warnings() {
+    let source = "terminating state main() {}\n";
+    let diagnostic = Diagnostic::warning(ErrorCode::UnreachableState, "Never reached", 0..11);
+    assert!(!diagnostic.is_error());
+    let report = render(&diagnostic, source);
+    assert!(
+      report.contains("[W0001] Warning: Never reached"),
+      "{}",
+      report
+    );
+  }
+}
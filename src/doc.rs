@@ -0,0 +1,91 @@
+use crate::{
+  graph::{EdgeKind, StateGraph},
+  types::Statement,
+};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+
+/// The name of the custom section compiled programs keep their documentation
+/// in
+pub const DOCS_SECTION: &str = "SycDocs";
+
+/// The documentation of a state. Compiled programs have the `StateDoc` of
+/// every state encoded into their `SycDocs` custom section.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StateDoc {
+  pub name: String,
+  /// How the state is declared, e.g. `terminating state retry(attempts: i32)`
+  pub signature: String,
+  /// The text of the `///` comments on the state, one line per comment
+  pub docs: String,
+}
+
+impl StateDoc {
+  /// Get the documentation of every state in a program in the order they are
+  /// defined
+  pub fn collect(program: &[Statement]) -> Vec<Self> {
+    program
+      .iter()
+      .filter_map(|stmt| match stmt {
+        Statement::StateDefn {
+          docs,
+          terminating,
+          name,
+          input,
+          ..
+        } => {
+          let params: Vec<String> = input
+            .iter()
+            .map(|param| format!("{}: {}", param.name.as_str(), param.ty))
+            .collect();
+          Some(Self {
+            name: name.as_str().to_string(),
+            signature: format!(
+              "{}state {}({})",
+              if *terminating { "terminating " } else { "" },
+              name.as_str(),
+              params.join(", ")
+            ),
+            docs: docs.join("\n"),
+          })
+        }
+        _ => None,
+      })
+      .collect()
+  }
+}
+
+/// Render Markdown documentation for a program with the given title. Every
+/// state gets a section with its signature, its doc comments and the states
+/// it calls or transitions to.
+pub fn render_markdown(title: &str, program: &[Statement]) -> String {
+  let graph = StateGraph::new(program);
+  let edges = graph.edges();
+  let mut out = String::new();
+  let _ = writeln!(out, "# {}", title);
+  for doc in StateDoc::collect(program) {
+    let _ = writeln!(out, "\n## `{}`\n", doc.name);
+    let _ = writeln!(out, "```\n{}\n```", doc.signature);
+    if !doc.docs.is_empty() {
+      let _ = writeln!(out, "\n{}", doc.docs);
+    }
+    let state = match graph.get(&doc.name) {
+      Some(state) => state,
+      None => continue,
+    };
+    for (kind, heading) in [
+      (EdgeKind::Transition, "Transitions to"),
+      (EdgeKind::Call, "Calls"),
+    ] {
+      let targets: Vec<String> = edges
+        .iter()
+        .filter(|(from, _, edge_kind)| *from == state && *edge_kind == kind)
+        .map(|(_, to, _)| format!("[`{0}`](#{0})", graph.states[*to].name.as_str()))
+        .collect();
+      if !targets.is_empty() {
+        let _ = writeln!(out, "\n{}: {}", heading, targets.join(", "));
+      }
+    }
+  }
+  out
+}
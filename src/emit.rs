@@ -0,0 +1,149 @@
+use crate::{context::SycContext, error::SycError, parser::Token, validate::validate};
+use logos::Logos;
+use std::{
+  fmt,
+  fmt::Write,
+  path::{Path, PathBuf},
+  str::FromStr,
+};
+
+/// The kinds of output that can be written when building a program
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmitKind {
+  /// The tokens the lexer splits the source into, one per line
+  Tokens,
+  /// The syntax tree of the program after its types are checked
+  Ast,
+  /// The compiled module as WebAssembly text
+  Wat,
+  /// The compiled module
+  Wasm,
+}
+
+impl EmitKind {
+  /// The extension of the file this kind of output is written to
+  pub fn extension(&self) -> &'static str {
+    match self {
+      EmitKind::Tokens => "tokens",
+      EmitKind::Ast => "ast",
+      EmitKind::Wat => "wat",
+      EmitKind::Wasm => "csm",
+    }
+  }
+}
+
+impl FromStr for EmitKind {
+  type Err = String;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "tokens" => Ok(EmitKind::Tokens),
+      "ast" => Ok(EmitKind::Ast),
+      "wat" => Ok(EmitKind::Wat),
+      "wasm" => Ok(EmitKind::Wasm),
+      _ => Err(format!(
+        "unknown output kind `{}`, expected one of tokens, ast, wat or wasm",
+        s
+      )),
+    }
+  }
+}
+
+impl fmt::Display for EmitKind {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let kind = match self {
+      EmitKind::Tokens => "tokens",
+      EmitKind::Ast => "ast",
+      EmitKind::Wat => "wat",
+      EmitKind::Wasm => "wasm",
+    };
+    write!(f, "{}", kind)
+  }
+}
+
+/// Where the output of building a program is written
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Output {
+  /// Next to the source file, named after it
+  NextToSource,
+  /// To this file. When more than one kind of output is emitted each one is
+  /// written here with its own extension.
+  File(PathBuf),
+  /// Into this directory, named after the source file
+  Dir(PathBuf),
+}
+
+impl Output {
+  /// Get the path to write one kind of output for the program at `source` to
+  /// when `count` kinds of output are being written
+  pub fn path_for(&self, source: &Path, kind: EmitKind, count: usize) -> PathBuf {
+    match self {
+      Output::NextToSource => source.with_extension(kind.extension()),
+      Output::File(file) if count == 1 => file.clone(),
+      Output::File(file) => file.with_extension(kind.extension()),
+      Output::Dir(dir) => {
+        let name = Path::new(source.file_name().unwrap_or(source.as_os_str()));
+        dir.join(name.with_extension(kind.extension()))
+      }
+    }
+  }
+}
+
+/// List every token in the source with its span and text, one per line
+pub fn tokens(input: &str) -> String {
+  let mut out = String::new();
+  for (token, span) in Token::lexer(input).spanned() {
+    let _ = writeln!(
+      out,
+      "{}..{} {:?} {:?}",
+      span.start, span.end, token, &input[span]
+    );
+  }
+  out
+}
+
+/// The WebAssembly proposals wabt has to accept for compiled modules. String
+/// builtins copy memory with `memory.copy` from bulk memory.
+fn features() -> wabt::Features {
+  let mut features = wabt::Features::new();
+  features.enable_bulk_memory();
+  features
+}
+
+/// Convert a compiled module to WebAssembly text, keeping the names from its
+/// name section
+pub fn wasm_to_wat(wasm: &[u8]) -> Result<String, wabt::Error> {
+  wabt::Wasm2Wat::new()
+    .read_debug_names(true)
+    .features(features())
+    .convert(wasm)
+    .map(|buf| String::from_utf8_lossy(buf.as_ref()).into_owned())
+}
+
+/// Compile WebAssembly text, such as a hand edited `wat` output, back into a
+/// module that can be run. The custom sections of the original module are
+/// lost in the text, so an empty `SycContext` is added back in. This means
+/// the module runs with the default `SandboxPolicy` rather than the one it
+/// was compiled with, unless the host gives it another one.
+pub fn wat_to_wasm(wat: &str) -> Result<Vec<u8>, SycError> {
+  let mut wasm = wabt::Wat2Wasm::new()
+    .write_debug_names(true)
+    .features(features())
+    .convert(wat)
+    .map(|buf| buf.as_ref().to_vec())
+    .map_err(|e| SycError::Wat(e.to_string()))?;
+  validate(&wasm).map_err(|invalid| {
+    SycError::Wat(match invalid.function {
+      Some(function) => format!("{} in function `{}`", invalid.message, function),
+      None => invalid.message,
+    })
+  })?;
+  let ctx = bincode::serialize(&SycContext::new()).map_err(|e| SycError::Wat(e.to_string()))?;
+  let mut section = wasm_encoder::Module::new();
+  section.section(&wasm_encoder::CustomSection {
+    name: "SycContext",
+    data: &ctx,
+  });
+  // Skip the magic number and version of the module the section is in
+  wasm.extend_from_slice(&section.finish()[8..]);
+  Ok(wasm)
+}
@@ -0,0 +1,259 @@
+use crate::diagnostics::{Diagnostic, Diagnostics};
+use std::{error::Error, fmt, io, time::Duration};
+use wasmtime::Trap;
+
+/// The phase of compilation a problem was found in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Phase {
+  Lex,
+  Parse,
+  Semantic,
+  Codegen,
+}
+
+/// A stable code for every kind of `Diagnostic` the compiler can produce so
+/// that tools can match on them without parsing messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+  /// Text that is not a valid sycamore token
+  UnknownToken,
+  /// A number literal that does not fit in its type
+  InvalidNumber,
+  /// An escape sequence in a string literal that is not valid
+  InvalidEscape,
+  /// A token that was not expected where it was found
+  UnexpectedToken,
+  /// The file ended in the middle of an item
+  UnexpectedEof,
+  /// A block that is never closed with a `}`
+  UnclosedBlock,
+  /// A type name that does not exist
+  UnknownType,
+  /// A `///` doc comment that is not followed by a state
+  DanglingDocComment,
+  /// `main` was not declared as a terminating state
+  MainNotTerminating,
+  /// `main` was declared with arguments
+  MainHasArgs,
+  /// A reference to a state that does not exist
+  UnknownState,
+  /// A reference to a variable that does not exist
+  UnknownVariable,
+  /// A state called with the wrong number of arguments
+  ArityMismatch,
+  /// A value of one type used where another type is needed
+  TypeMismatch,
+  /// Two states defined with the same name
+  DuplicateState,
+  /// A parameter or variable declared twice in the same state
+  DuplicateVariable,
+  /// A variable read before a value is assigned to it
+  UseBeforeAssign,
+  /// A state defined inside of another state
+  NestedState,
+  /// The program has no `main` state to start in
+  MissingMain,
+  /// `terminate` used in a state that is not terminating
+  TerminateInNonTerminating,
+  /// A state that is transitioned to but never leaves
+  DeadEndState,
+  /// A loop of states that can never reach a terminating state
+  InfiniteLoop,
+  /// A terminating state that can never be reached from `main`
+  UnreachableState,
+  /// The compiler produced something it could not finish
+  InternalError,
+}
+
+impl ErrorCode {
+  /// Get the code as a string, e.g. `E0001`. Codes for problems that are
+  /// only ever warnings start with a `W`
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      ErrorCode::UnknownToken => "E0001",
+      ErrorCode::InvalidNumber => "E0002",
+      ErrorCode::InvalidEscape => "E0003",
+      ErrorCode::UnexpectedToken => "E0100",
+      ErrorCode::UnexpectedEof => "E0101",
+      ErrorCode::UnclosedBlock => "E0102",
+      ErrorCode::UnknownType => "E0103",
+      ErrorCode::DanglingDocComment => "E0104",
+      ErrorCode::MainNotTerminating => "E0200",
+      ErrorCode::MainHasArgs => "E0201",
+      ErrorCode::UnknownState => "E0202",
+      ErrorCode::NestedState => "E0203",
+      ErrorCode::MissingMain => "E0204",
+      ErrorCode::TerminateInNonTerminating => "E0205",
+      ErrorCode::DeadEndState => "E0206",
+      ErrorCode::InfiniteLoop => "E0207",
+      ErrorCode::UnknownVariable => "E0208",
+      ErrorCode::ArityMismatch => "E0209",
+      ErrorCode::TypeMismatch => "E0210",
+      ErrorCode::DuplicateState => "E0211",
+      ErrorCode::DuplicateVariable => "E0212",
+      ErrorCode::UseBeforeAssign => "E0213",
+      ErrorCode::UnreachableState => "W0001",
+      ErrorCode::InternalError => "E0300",
+    }
+  }
+
+  /// Get the phase of compilation this kind of problem is found in
+  pub fn phase(&self) -> Phase {
+    match self {
+      ErrorCode::UnknownToken | ErrorCode::InvalidNumber | ErrorCode::InvalidEscape => Phase::Lex,
+      ErrorCode::UnexpectedToken
+      | ErrorCode::UnexpectedEof
+      | ErrorCode::UnclosedBlock
+      | ErrorCode::UnknownType
+      | ErrorCode::DanglingDocComment => Phase::Parse,
+      ErrorCode::MainNotTerminating
+      | ErrorCode::MainHasArgs
+      | ErrorCode::UnknownState
+      | ErrorCode::UnknownVariable
+      | ErrorCode::ArityMismatch
+      | ErrorCode::TypeMismatch
+      | ErrorCode::DuplicateState
+      | ErrorCode::DuplicateVariable
+      | ErrorCode::UseBeforeAssign
+      | ErrorCode::NestedState
+      | ErrorCode::MissingMain
+      | ErrorCode::TerminateInNonTerminating
+      | ErrorCode::DeadEndState
+      | ErrorCode::InfiniteLoop
+      | ErrorCode::UnreachableState => Phase::Semantic,
+      ErrorCode::InternalError => Phase::Codegen,
+    }
+  }
+}
+
+impl fmt::Display for ErrorCode {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.as_str())
+  }
+}
+
+/// Every way building or running a sycamore program can fail
+#[derive(Debug)]
+pub enum SycError {
+  /// The source contained text that is not valid sycamore
+  Lex(Vec<Diagnostic>),
+  /// The source is not a syntactically valid program
+  Parse(Vec<Diagnostic>),
+  /// The program is syntactically valid but does not make sense
+  Semantic(Vec<Diagnostic>),
+  /// Code generation for the program failed
+  Codegen(Vec<Diagnostic>),
+  /// The compiled module could not be loaded or linked with the host
+  Link(Box<dyn Error + Send + Sync>),
+  /// The program trapped while it was running
+  Trap(Trap),
+  /// The program used up all of its fuel, while running `state` if it is
+  /// known
+  OutOfFuel { fuel: u64, state: Option<String> },
+  /// The program ran for longer than it was allowed to, and was running
+  /// `state` if it is known
+  Timeout {
+    timeout: Duration,
+    state: Option<String>,
+  },
+  /// WebAssembly text could not be compiled into a module
+  Wat(String),
+  /// Reading or writing a file failed
+  Io(io::Error),
+}
+
+impl SycError {
+  /// Create a `SycError` from the `Diagnostic`s of a failed compilation. The
+  /// variant is picked from the earliest phase of compilation that failed,
+  /// but all of the `Diagnostic`s are kept.
+  pub fn from_diagnostics(diagnostics: Vec<Diagnostic>) -> Self {
+    let phase = diagnostics
+      .iter()
+      .filter(|d| d.is_error())
+      .map(|d| d.code.phase())
+      .min()
+      .unwrap_or(Phase::Codegen);
+    match phase {
+      Phase::Lex => SycError::Lex(diagnostics),
+      Phase::Parse => SycError::Parse(diagnostics),
+      Phase::Semantic => SycError::Semantic(diagnostics),
+      Phase::Codegen => SycError::Codegen(diagnostics),
+    }
+  }
+
+  /// Get the `Diagnostic`s of a failed compilation. This is empty for errors
+  /// that do not come from the compiler.
+  pub fn diagnostics(&self) -> &[Diagnostic] {
+    match self {
+      SycError::Lex(d) | SycError::Parse(d) | SycError::Semantic(d) | SycError::Codegen(d) => d,
+      SycError::Link(_)
+      | SycError::Trap(_)
+      | SycError::OutOfFuel { .. }
+      | SycError::Timeout { .. }
+      | SycError::Wat(_)
+      | SycError::Io(_) => &[],
+    }
+  }
+}
+
+impl fmt::Display for SycError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      SycError::Lex(_) | SycError::Parse(_) | SycError::Semantic(_) | SycError::Codegen(_) => {
+        match self.diagnostics().iter().filter(|d| d.is_error()).count() {
+          1 => write!(f, "could not compile due to previous error"),
+          n => write!(f, "could not compile due to {} previous errors", n),
+        }
+      }
+      SycError::Link(e) => write!(f, "could not load the compiled module: {}", e),
+      SycError::Trap(trap) => write!(f, "the program trapped: {}", trap),
+      SycError::OutOfFuel { fuel, state } => {
+        write!(f, "the program ran out of fuel after using {} units", fuel)?;
+        write_state(f, state)
+      }
+      SycError::Timeout { timeout, state } => {
+        write!(f, "the program was stopped after running for {:?}", timeout)?;
+        write_state(f, state)
+      }
+      SycError::Wat(e) => write!(f, "could not compile the WebAssembly text: {}", e),
+      SycError::Io(e) => write!(f, "{}", e),
+    }
+  }
+}
+
+/// Write which state was running when a program was stopped, if it is known
+fn write_state(f: &mut fmt::Formatter<'_>, state: &Option<String>) -> fmt::Result {
+  match state {
+    Some(state) => write!(f, " in state `{}`", state),
+    None => Ok(()),
+  }
+}
+
+impl Error for SycError {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    match self {
+      SycError::Link(e) => Some(e.as_ref()),
+      SycError::Trap(trap) => Some(trap),
+      SycError::Io(e) => Some(e),
+      _ => None,
+    }
+  }
+}
+
+impl From<Diagnostics> for SycError {
+  fn from(diagnostics: Diagnostics) -> Self {
+    SycError::from_diagnostics(diagnostics.list)
+  }
+}
+
+impl From<io::Error> for SycError {
+  fn from(e: io::Error) -> Self {
+    SycError::Io(e)
+  }
+}
+
+impl From<Trap> for SycError {
+  fn from(trap: Trap) -> Self {
+    SycError::Trap(trap)
+  }
+}
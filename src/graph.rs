@@ -0,0 +1,762 @@
+use crate::{
+  diagnostics::Diagnostic,
+  error::ErrorCode,
+  types::{Ident, Statement},
+};
+use std::{
+  collections::{HashMap, HashSet, VecDeque},
+  fmt::Write,
+  ops::Range,
+  str::FromStr,
+};
+
+/// How one state moves to another
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeKind {
+  /// The state is called and control comes back afterwards
+  Call,
+  /// The machine leaves the state for the other one with `goto`
+  Transition,
+}
+
+/// A call or transition from one state to another
+#[derive(Debug, Clone)]
+pub struct Edge {
+  /// The state being moved to
+  pub target: Ident,
+  pub kind: EdgeKind,
+}
+
+/// A state in the `StateGraph`
+#[derive(Debug, Clone)]
+pub struct StateNode {
+  pub name: Ident,
+  pub terminating: bool,
+  /// Every call or transition made in the state in the order they appear
+  pub edges: Vec<Edge>,
+  /// The spans of every `terminate` statement in the state
+  pub terminates: Vec<Range<usize>>,
+}
+
+/// The state machine described by a sycamore program. Each state is a node
+/// and every call or transition out of it is an edge.
+#[derive(Debug, Clone)]
+pub struct StateGraph {
+  pub states: Vec<StateNode>,
+  index: HashMap<String, usize>,
+}
+
+impl StateGraph {
+  /// Build the `StateGraph` for a program
+  pub fn new(program: &[Statement]) -> Self {
+    let mut states = Vec::new();
+    let mut index = HashMap::new();
+    for stmt in program {
+      if let Statement::StateDefn {
+        name,
+        terminating,
+        statements,
+        ..
+      } = stmt
+      {
+        let mut node = StateNode {
+          name: name.clone(),
+          terminating: *terminating,
+          edges: Vec::new(),
+          terminates: Vec::new(),
+        };
+        collect(statements, &mut node);
+        index
+          .entry(name.as_str().to_string())
+          .or_insert(states.len());
+        states.push(node);
+      }
+    }
+    Self { states, index }
+  }
+
+  /// Get the index of the state with the given name
+  pub fn get(&self, name: &str) -> Option<usize> {
+    self.index.get(name).copied()
+  }
+
+  /// Get the indices of every state each state has an edge to. Edges to
+  /// states that do not exist are skipped.
+  fn successors(&self) -> Vec<Vec<usize>> {
+    self
+      .states
+      .iter()
+      .map(|state| {
+        state
+          .edges
+          .iter()
+          .filter_map(|edge| self.get(edge.target.as_str()))
+          .collect()
+      })
+      .collect()
+  }
+
+  /// Get the indices of the states each state can hand the machine over to.
+  /// That is every `goto`, along with calls to states that can `terminate` or
+  /// `goto` somewhere, since the caller goes along with them when they do. A
+  /// call to a state that always returns comes back to the caller, so it is
+  /// left out.
+  fn moves(&self) -> Vec<Vec<usize>> {
+    let mut callers = vec![Vec::new(); self.states.len()];
+    for (from, state) in self.states.iter().enumerate() {
+      for edge in state
+        .edges
+        .iter()
+        .filter(|edge| edge.kind == EdgeKind::Call)
+      {
+        if let Some(to) = self.get(edge.target.as_str()) {
+          callers[to].push(from);
+        }
+      }
+    }
+    // States that can leave rather than return, either themselves or
+    // through a state they call
+    let mut leaves = HashSet::new();
+    let mut queue: VecDeque<usize> = (0..self.states.len())
+      .filter(|idx| {
+        let state = &self.states[*idx];
+        !state.terminates.is_empty()
+          || state
+            .edges
+            .iter()
+            .any(|edge| edge.kind == EdgeKind::Transition)
+      })
+      .collect();
+    while let Some(state) = queue.pop_front() {
+      if leaves.insert(state) {
+        queue.extend(callers[state].iter().copied());
+      }
+    }
+
+    self
+      .states
+      .iter()
+      .map(|state| {
+        state
+          .edges
+          .iter()
+          .filter_map(|edge| Some((edge.kind, self.get(edge.target.as_str())?)))
+          .filter(|(kind, to)| *kind == EdgeKind::Transition || leaves.contains(to))
+          .map(|(_, to)| to)
+          .collect()
+      })
+      .collect()
+  }
+
+  /// Check the state machine for states that can never be used properly:
+  ///
+  /// - `terminate` used in a state that is not terminating
+  /// - terminating states that can never be reached from `main`
+  /// - non-terminating states that are transitioned to but never leave
+  /// - loops of states that can never reach a terminating state
+  pub fn check(&self) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+
+    for state in self.states.iter().filter(|s| !s.terminating) {
+      for span in &state.terminates {
+        diagnostics.push(
+          Diagnostic::error(
+            ErrorCode::TerminateInNonTerminating,
+            format!(
+              "`terminate` used in non-terminating state `{}`",
+              state.name.as_str()
+            ),
+            span.clone(),
+          )
+          .with_label(span.clone(), "the program cannot end here")
+          .with_label(state.name.span(), "this state is not terminating")
+          .with_note("help: declare the state with `terminating state`"),
+        );
+      }
+    }
+
+    // Dead ends are states that the machine moves into with `goto` but that
+    // never go anywhere else, so the machine would stop in the middle
+    let moves = self.moves();
+    let transitioned_to: HashSet<&str> = self
+      .states
+      .iter()
+      .flat_map(|s| s.edges.iter())
+      .filter(|edge| edge.kind == EdgeKind::Transition)
+      .map(|edge| edge.target.as_str())
+      .collect();
+    for (idx, state) in self.states.iter().enumerate() {
+      if !state.terminating
+        && moves[idx].is_empty()
+        && transitioned_to.contains(state.name.as_str())
+      {
+        diagnostics.push(
+          Diagnostic::error(
+            ErrorCode::DeadEndState,
+            format!("state `{}` is a dead end", state.name.as_str()),
+            state.name.span(),
+          )
+          .with_label(
+            state.name.span(),
+            "this state is transitioned to but never goes anywhere else",
+          )
+          .with_note("help: add a `goto` to another state or make it a `terminating state`"),
+        );
+      }
+    }
+
+    // Without `main` nothing is reachable, the resolver reports it missing
+    let main = match self.get("main") {
+      Some(main) => main,
+      None => return diagnostics,
+    };
+
+    let reachable = reachable_from(&self.successors(), Some(main));
+    for (idx, state) in self.states.iter().enumerate() {
+      if state.terminating && !reachable.contains(&idx) {
+        diagnostics.push(
+          Diagnostic::warning(
+            ErrorCode::UnreachableState,
+            format!(
+              "terminating state `{}` is never reached",
+              state.name.as_str()
+            ),
+            state.name.span(),
+          )
+          .with_label(state.name.span(), "no path from `main` leads here"),
+        );
+      }
+    }
+
+    // Work out which states can reach a terminating state by walking the
+    // moves backwards from all of them. Calls that come back are left out, so
+    // a state that calls itself until it returns doesn't loop forever.
+    let mut predecessors = vec![Vec::new(); self.states.len()];
+    for (state, next) in moves.iter().enumerate() {
+      for next in next {
+        predecessors[*next].push(state);
+      }
+    }
+    let mut can_finish = HashSet::new();
+    let mut queue: VecDeque<usize> = (0..self.states.len())
+      .filter(|idx| self.states[*idx].terminating)
+      .collect();
+    while let Some(state) = queue.pop_front() {
+      if can_finish.insert(state) {
+        queue.extend(predecessors[state].iter().copied());
+      }
+    }
+
+    // Any loop made only of states that can never finish runs forever. Each
+    // loop is reported once, at the state in it that is defined first.
+    let mut reported = HashSet::new();
+    for idx in 0..self.states.len() {
+      if can_finish.contains(&idx) || reported.contains(&idx) {
+        continue;
+      }
+      let from_here = reachable_from(&moves, moves[idx].iter().copied());
+      if !from_here.contains(&idx) {
+        continue;
+      }
+      let mut cycle: Vec<usize> = from_here
+        .into_iter()
+        .filter(|other| reachable_from(&moves, moves[*other].iter().copied()).contains(&idx))
+        .collect();
+      cycle.sort_unstable();
+      reported.extend(cycle.iter().copied());
+
+      let state = &self.states[idx];
+      let mut diagnostic = Diagnostic::error(
+        ErrorCode::InfiniteLoop,
+        format!(
+          "state `{}` loops forever without reaching a terminating state",
+          state.name.as_str()
+        ),
+        state.name.span(),
+      )
+      .with_label(state.name.span(), "this state is part of the loop");
+      for other in cycle.into_iter().filter(|other| *other != idx) {
+        let other = &self.states[other].name;
+        diagnostic = diagnostic.with_label(other.span(), "this state is also part of the loop");
+      }
+      diagnostics
+        .push(diagnostic.with_note("help: add a `goto` out of the loop to a terminating state"));
+    }
+
+    diagnostics
+  }
+}
+
+/// The formats a `StateGraph` can be exported to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphFormat {
+  /// Graphviz DOT
+  Dot,
+  /// A Mermaid state diagram
+  Mermaid,
+  /// JSON with a list of states and a list of edges
+  Json,
+}
+
+impl FromStr for GraphFormat {
+  type Err = String;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "dot" => Ok(GraphFormat::Dot),
+      "mermaid" => Ok(GraphFormat::Mermaid),
+      "json" => Ok(GraphFormat::Json),
+      _ => Err(format!(
+        "unknown graph format `{}`, expected one of dot, mermaid or json",
+        s
+      )),
+    }
+  }
+}
+
+impl StateGraph {
+  /// Render the `StateGraph` in the given format
+  pub fn render(&self, format: GraphFormat) -> String {
+    match format {
+      GraphFormat::Dot => self.to_dot(),
+      GraphFormat::Mermaid => self.to_mermaid(),
+      GraphFormat::Json => self.to_json(),
+    }
+  }
+
+  /// Get every edge between two states that exist as `(from, to, kind)`.
+  /// Making the same call or transition more than once in a state only shows
+  /// up as one edge.
+  pub fn edges(&self) -> Vec<(usize, usize, EdgeKind)> {
+    let mut edges = Vec::new();
+    for (from, state) in self.states.iter().enumerate() {
+      for edge in &state.edges {
+        if let Some(to) = self.get(edge.target.as_str()) {
+          if !edges.contains(&(from, to, edge.kind)) {
+            edges.push((from, to, edge.kind));
+          }
+        }
+      }
+    }
+    edges
+  }
+
+  /// Render the `StateGraph` as Graphviz DOT. `main` has an arrow coming in
+  /// from nowhere to mark it as the initial state, terminating states are
+  /// drawn with a double circle and calls are drawn with dashed lines.
+  pub fn to_dot(&self) -> String {
+    let mut out = String::new();
+    out.push_str("digraph sycamore {\n");
+    out.push_str("  rankdir=LR;\n");
+    out.push_str("  node [shape=circle];\n");
+    if let Some(main) = self.get("main") {
+      out.push_str("  \"__start\" [shape=point];\n");
+      let _ = writeln!(out, "  \"__start\" -> s{};", main);
+    }
+    for (idx, state) in self.states.iter().enumerate() {
+      let shape = if state.terminating {
+        "doublecircle"
+      } else {
+        "circle"
+      };
+      let _ = writeln!(
+        out,
+        "  s{} [label={}, shape={}];",
+        idx,
+        quote(state.name.as_str()),
+        shape
+      );
+    }
+    for (from, to, kind) in self.edges() {
+      let style = match kind {
+        EdgeKind::Transition => "[label=\"goto\"]",
+        EdgeKind::Call => "[label=\"call\", style=dashed]",
+      };
+      let _ = writeln!(out, "  s{} -> s{} {};", from, to, style);
+    }
+    out.push_str("}\n");
+    out
+  }
+
+  /// Render the `StateGraph` as a Mermaid state diagram. `main` is entered
+  /// from the start marker, terminating states lead to the end marker and are
+  /// styled with the `terminating` class.
+  pub fn to_mermaid(&self) -> String {
+    let mut out = String::new();
+    out.push_str("stateDiagram-v2\n");
+    for (idx, state) in self.states.iter().enumerate() {
+      let _ = writeln!(out, "  state {} as s{}", quote(state.name.as_str()), idx);
+    }
+    if let Some(main) = self.get("main") {
+      let _ = writeln!(out, "  [*] --> s{}", main);
+    }
+    for (from, to, kind) in self.edges() {
+      let label = match kind {
+        EdgeKind::Transition => "goto",
+        EdgeKind::Call => "call",
+      };
+      let _ = writeln!(out, "  s{} --> s{}: {}", from, to, label);
+    }
+    let terminating: Vec<String> = (0..self.states.len())
+      .filter(|idx| self.states[*idx].terminating)
+      .map(|idx| format!("s{}", idx))
+      .collect();
+    for state in &terminating {
+      let _ = writeln!(out, "  {} --> [*]", state);
+    }
+    if !terminating.is_empty() {
+      out.push_str("  classDef terminating font-weight:bold,stroke-width:3px\n");
+      let _ = writeln!(out, "  class {} terminating", terminating.join(","));
+    }
+    out
+  }
+
+  /// Render the `StateGraph` as JSON
+  pub fn to_json(&self) -> String {
+    let states: Vec<String> = self
+      .states
+      .iter()
+      .map(|state| {
+        format!(
+          "{{\"name\":{},\"terminating\":{},\"initial\":{}}}",
+          quote(state.name.as_str()),
+          state.terminating,
+          state.name.as_str() == "main"
+        )
+      })
+      .collect();
+    let edges: Vec<String> = self
+      .edges()
+      .into_iter()
+      .map(|(from, to, kind)| {
+        let kind = match kind {
+          EdgeKind::Transition => "transition",
+          EdgeKind::Call => "call",
+        };
+        format!(
+          "{{\"from\":{},\"to\":{},\"kind\":\"{}\"}}",
+          quote(self.states[from].name.as_str()),
+          quote(self.states[to].name.as_str()),
+          kind
+        )
+      })
+      .collect();
+    format!(
+      "{{\"states\":[{}],\"edges\":[{}]}}\n",
+      states.join(","),
+      edges.join(",")
+    )
+  }
+}
+
+/// Quote a name so it can be used as a string in DOT, Mermaid or JSON
+fn quote(name: &str) -> String {
+  let mut quoted = String::with_capacity(name.len() + 2);
+  quoted.push('"');
+  for c in name.chars() {
+    match c {
+      '"' => quoted.push_str("\\\""),
+      '\\' => quoted.push_str("\\\\"),
+      c => quoted.push(c),
+    }
+  }
+  quoted.push('"');
+  quoted
+}
+
+/// Find every state that can be reached from the given ones by following
+/// `edges`
+fn reachable_from(edges: &[Vec<usize>], start: impl IntoIterator<Item = usize>) -> HashSet<usize> {
+  let mut seen = HashSet::new();
+  let mut queue: VecDeque<usize> = start.into_iter().collect();
+  while let Some(state) = queue.pop_front() {
+    if seen.insert(state) {
+      queue.extend(edges[state].iter().copied());
+    }
+  }
+  seen
+}
+
+/// Collect the edges and `terminate` statements in a block of statements
+fn collect(statements: &[Statement], node: &mut StateNode) {
+  for stmt in statements {
+    match stmt {
+      Statement::FnCall { name, .. } => node.edges.push(Edge {
+        target: name.clone(),
+        kind: EdgeKind::Call,
+      }),
+      Statement::Transition { name, .. } => node.edges.push(Edge {
+        target: name.clone(),
+        kind: EdgeKind::Transition,
+      }),
+      Statement::Terminate { span, .. } => node.terminates.push(span.clone()),
+      Statement::If {
+        then_block,
+        else_block,
+        ..
+      } => {
+        collect(then_block, node);
+        collect(else_block, node);
+      }
+      Statement::Assignment { .. } | Statement::Wasi(_) | Statement::StateDefn { .. } => (),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::parser::SycParser;
+
+  /// Check the state machine of a program that parses cleanly, returning the
+  /// codes of the problems found
+  fn check(source: &str) -> Vec<ErrorCode> {
+    let (program, errors) = SycParser::new(source).parse();
+    assert!(errors.is_empty(), "{:?}", errors);
+    StateGraph::new(&program)
+      .check()
+      .into_iter()
+      .map(|diagnostic| diagnostic.code)
+      .collect()
+  }
+
+  #[test]
+  fn recursive_call_is_not_a_loop() {
+    let source = r#"
+      terminating state main() {
+        countdown(3);
+      }
+      state countdown(n: i32) {
+        if n equals 0 {
+          println("done");
+        } else {
+          countdown(n - 1);
+        }
+      }
+    "#;
+    assert_eq!(check(source), Vec::new());
+  }
+
+  #[test]
+  fn goto_cycle_is_a_loop() {
+    let source = r#"
+      terminating state main() {
+        goto ping;
+      }
+      state ping() {
+        goto pong;
+      }
+      state pong() {
+        goto ping;
+      }
+    "#;
+    assert_eq!(check(source), vec![ErrorCode::InfiniteLoop]);
+  }
+
+  #[test]
+  fn calling_a_state_that_terminates_can_finish() {
+    let source = r#"
+      terminating state main() {
+        goto retry;
+      }
+      state retry() {
+        quit();
+        goto retry;
+      }
+      terminating state quit() {
+        terminate 1;
+      }
+    "#;
+    assert_eq!(check(source), Vec::new());
+  }
+
+  #[test]
+  fn calling_a_state_that_returns_does_not_finish() {
+    let source = r#"
+      terminating state main() {
+        goto retry;
+      }
+      state retry() {
+        log();
+        goto retry;
+      }
+      terminating state log() {
+        println("retrying");
+      }
+    "#;
+    assert_eq!(check(source), vec![ErrorCode::InfiniteLoop]);
+  }
+
+  #[test]
+  fn state_that_only_calls_and_returns_is_a_dead_end() {
+    let source = r#"
+      terminating state main() {
+        goto stuck;
+      }
+      state stuck() {
+        log();
+      }
+      terminating state log() {
+        println("stuck");
+      }
+    "#;
+    assert_eq!(check(source), vec![ErrorCode::DeadEndState]);
+  }
+
+  #[test]
+  fn state_that_is_only_called_is_not_a_dead_end() {
+    let source = r#"
+      terminating state main() {
+        log();
+      }
+      state log() {
+        println("called");
+      }
+    "#;
+    assert_eq!(check(source), Vec::new());
+  }
+
+  #[test]
+  fn terminating_state_nothing_leads_to_is_unreachable() {
+    let source = r#"
+      terminating state main() {
+        terminate;
+      }
+      terminating state unused() {
+        terminate;
+      }
+    "#;
+    assert_eq!(check(source), vec![ErrorCode::UnreachableState]);
+  }
+
+  #[test]
+  fn called_terminating_state_is_reachable() {
+    let source = r#"
+      terminating state main() {
+        done();
+      }
+      terminating state done() {
+        terminate;
+      }
+    "#;
+    assert_eq!(check(source), Vec::new());
+  }
+
+  #[test]
+  fn terminate_outside_a_terminating_state_is_an_error() {
+    let source = r#"
+      terminating state main() {
+        goto work;
+      }
+      state work() {
+        terminate 1;
+        goto main;
+      }
+    "#;
+    assert_eq!(check(source), vec![ErrorCode::TerminateInNonTerminating]);
+  }
+
+  #[test]
+  fn terminate_in_a_terminating_state_is_allowed() {
+    let source = r#"
+      terminating state main() {
+        goto work;
+      }
+      terminating state work() {
+        terminate 1;
+      }
+    "#;
+    assert_eq!(check(source), Vec::new());
+  }
+
+  /// A state that calls and moves to another state, which moves back
+  const TWO_STATES: &str = r#"
+    terminating state main() {
+      greet();
+      goto greet;
+    }
+    state greet() {
+      goto main;
+    }
+  "#;
+
+  /// Build the state graph of a program that parses cleanly
+  fn graph(source: &str) -> StateGraph {
+    let (program, errors) = SycParser::new(source).parse();
+    assert!(errors.is_empty(), "{:?}", errors);
+    StateGraph::new(&program)
+  }
+
+  #[test]
+  fn dot_dashes_calls() {
+    assert_eq!(
+      graph(TWO_STATES).to_dot(),
+      concat!(
+        "digraph sycamore {\n",
+        "  rankdir=LR;\n",
+        "  node [shape=circle];\n",
+        "  \"__start\" [shape=point];\n",
+        "  \"__start\" -> s0;\n",
+        "  s0 [label=\"main\", shape=doublecircle];\n",
+        "  s1 [label=\"greet\", shape=circle];\n",
+        "  s0 -> s1 [label=\"call\", style=dashed];\n",
+        "  s0 -> s1 [label=\"goto\"];\n",
+        "  s1 -> s0 [label=\"goto\"];\n",
+        "}\n",
+      )
+    );
+  }
+
+  #[test]
+  fn mermaid_labels_calls_and_gotos() {
+    assert_eq!(
+      graph(TWO_STATES).to_mermaid(),
+      concat!(
+        "stateDiagram-v2\n",
+        "  state \"main\" as s0\n",
+        "  state \"greet\" as s1\n",
+        "  [*] --> s0\n",
+        "  s0 --> s1: call\n",
+        "  s0 --> s1: goto\n",
+        "  s1 --> s0: goto\n",
+        "  s0 --> [*]\n",
+        "  classDef terminating font-weight:bold,stroke-width:3px\n",
+        "  class s0 terminating\n",
+      )
+    );
+  }
+
+  #[test]
+  fn json_gives_the_kind_of_each_edge() {
+    assert_eq!(
+      graph(TWO_STATES).to_json(),
+      concat!(
+        "{\"states\":[",
+        "{\"name\":\"main\",\"terminating\":true,\"initial\":true},",
+        "{\"name\":\"greet\",\"terminating\":false,\"initial\":false}",
+        "],\"edges\":[",
+        "{\"from\":\"main\",\"to\":\"greet\",\"kind\":\"call\"},",
+        "{\"from\":\"main\",\"to\":\"greet\",\"kind\":\"transition\"},",
+        "{\"from\":\"greet\",\"to\":\"main\",\"kind\":\"transition\"}",
+        "]}\n",
+      )
+    );
+  }
+
+  #[test]
+  fn state_names_are_escaped() {
+    let program = [Statement::StateDefn {
+      docs: Vec::new(),
+      terminating: true,
+      name: Ident::new(r#"say "hi" \ bye"#, 0..0),
+      input: Vec::new(),
+      statements: Vec::new(),
+    }];
+    let quoted = r#""say \"hi\" \\ bye""#;
+    let graph = StateGraph::new(&program);
+    assert!(graph.to_dot().contains(&format!("label={}", quoted)));
+    assert!(graph
+      .to_mermaid()
+      .contains(&format!("state {} as s0", quoted)));
+    assert!(graph.to_json().contains(&format!("\"name\":{}", quoted)));
+  }
+}
@@ -0,0 +1,363 @@
+use crate::{
+  context::SycContext,
+  error::SycError,
+  sandbox::{Capability, SandboxOverrides, SandboxPolicy},
+  wasi::wasi_linker,
+};
+use std::{
+  io::{Cursor, Read, Write},
+  path::PathBuf,
+  sync::mpsc::{self, RecvTimeoutError},
+  thread,
+  time::Duration,
+};
+use wasi_common::pipe::{ReadPipe, WritePipe};
+use wasmtime::{Config, Engine, Linker, Module, Store, Trap, TrapCode};
+use wasmtime_wasi::sync::{ambient_authority, dir::Dir as SyncDir, Dir, WasiCtxBuilder};
+
+/// Where a program reads its standard input from
+pub enum InputPipe {
+  /// The standard input of the host process
+  Inherit,
+  /// These bytes, followed by the end of the input
+  Bytes(Vec<u8>),
+  /// Anything that can be read from
+  Reader(Box<dyn Read + Send + Sync>),
+}
+
+/// Where a program writes its standard output or standard error to
+pub enum OutputPipe {
+  /// The same stream of the host process
+  Inherit,
+  /// A buffer that is returned in the `RunOutput` once the program exits
+  Capture,
+  /// Anything that can be written to
+  Writer(Box<dyn Write + Send + Sync>),
+}
+
+/// The host environment a program runs in. The program only gets the parts
+/// of it that its sandbox policy allows.
+pub struct RunOptions {
+  /// The arguments the program sees, starting with its name
+  pub args: Vec<String>,
+  /// The environment variables the program sees
+  pub env: Vec<(String, String)>,
+  pub stdin: InputPipe,
+  pub stdout: OutputPipe,
+  pub stderr: OutputPipe,
+  /// How much fuel the program can use before it is stopped. Every
+  /// instruction the program runs uses up some fuel. This needs a
+  /// `SycRuntime` made with [`SycRuntime::with_limits`].
+  pub fuel: Option<u64>,
+  /// How long the program can run for before it is stopped. This needs a
+  /// `SycRuntime` made with [`SycRuntime::with_limits`].
+  pub timeout: Option<Duration>,
+  /// The sandbox to run the program in instead of the one it was compiled
+  /// with. Without it the program gets no directories or environment
+  /// variables unless `sandbox_overrides` grants them.
+  pub sandbox: Option<SandboxPolicy>,
+  /// Parts of the sandbox to replace, on top of whichever one the program
+  /// runs in
+  pub sandbox_overrides: SandboxOverrides,
+}
+
+impl Default for RunOptions {
+  /// A program with no arguments or environment variables that uses the
+  /// standard streams of the host and the sandbox it was compiled with
+  fn default() -> Self {
+    Self {
+      args: Vec::new(),
+      env: Vec::new(),
+      stdin: InputPipe::Inherit,
+      stdout: OutputPipe::Inherit,
+      stderr: OutputPipe::Inherit,
+      fuel: None,
+      timeout: None,
+      sandbox: None,
+      sandbox_overrides: SandboxOverrides::default(),
+    }
+  }
+}
+
+/// The result of running a program to completion
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RunOutput {
+  /// The exit code the program ended with, which is 0 unless it used
+  /// `terminate` with a code
+  pub exit_code: i32,
+  /// Everything the program wrote to standard output if it was captured
+  pub stdout: Vec<u8>,
+  /// Everything the program wrote to standard error if it was captured
+  pub stderr: Vec<u8>,
+}
+
+/// A compiled sycamore program loaded into a `SycRuntime`, ready to be run
+/// any number of times
+#[derive(Clone)]
+pub struct Program {
+  module: Module,
+  context: Vec<u8>,
+}
+
+/// Runs sycamore programs. The `Engine` and WASI functions are set up once
+/// and shared by every program the `SycRuntime` loads and runs, so running
+/// many programs only costs compiling and instantiating each of them.
+pub struct SycRuntime {
+  engine: Engine,
+  linker: Linker<SycContext>,
+  /// Does the engine meter fuel, which is needed for a fuel limit
+  fuel: bool,
+  /// Can the engine interrupt programs, which is needed for a timeout
+  interruptable: bool,
+}
+
+impl SycRuntime {
+  /// Create a `SycRuntime` with a default `Engine`. Programs run with it
+  /// can't be given a fuel limit or a timeout, use
+  /// [`SycRuntime::with_limits`] for that.
+  pub fn new() -> Result<Self, SycError> {
+    Self::with_engine(Engine::default())
+  }
+
+  /// Create a `SycRuntime` with an `Engine` that can limit how much fuel
+  /// programs use and how long they run for. Programs run a bit slower on it
+  /// since they have to keep track of both.
+  pub fn with_limits() -> Result<Self, SycError> {
+    let mut config = Config::new();
+    config.consume_fuel(true).interruptable(true);
+    Self::with_engine(Engine::new(&config).map_err(|e| SycError::Link(e.into()))?)
+  }
+
+  /// Create a `SycRuntime` that uses the given `Engine`. Programs can only be
+  /// given a fuel limit if it is configured to consume fuel, and a timeout if
+  /// it is configured to be interruptable.
+  pub fn with_engine(engine: Engine) -> Result<Self, SycError> {
+    let mut linker = Linker::new(&engine);
+    wasi_linker(&mut linker)?;
+    // The config of an engine can't be read back, but a store shows what it
+    // was configured to do
+    let store = Store::new(&engine, ());
+    let fuel = store.fuel_consumed().is_some();
+    let interruptable = store.interrupt_handle().is_ok();
+    Ok(Self {
+      engine,
+      linker,
+      fuel,
+      interruptable,
+    })
+  }
+
+  /// The `Engine` programs are compiled and run with
+  pub fn engine(&self) -> &Engine {
+    &self.engine
+  }
+
+  /// Compile a sycamore binary so that it can be run
+  pub fn load(&self, csm: &[u8]) -> Result<Program, SycError> {
+    let context = SycContext::section(csm)?.to_vec();
+    let module = Module::new(&self.engine, csm).map_err(|e| SycError::Link(e.into()))?;
+    Ok(Program { module, context })
+  }
+
+  /// Compile and run a sycamore binary
+  pub fn run(&self, csm: &[u8], options: RunOptions) -> Result<RunOutput, SycError> {
+    self.run_program(&self.load(csm)?, options)
+  }
+
+  /// Run a program that was already loaded
+  pub fn run_program(&self, program: &Program, options: RunOptions) -> Result<RunOutput, SycError> {
+    if options.fuel.is_some() && !self.fuel {
+      return Err(SycError::Link(
+        "a fuel limit needs an engine that is configured to consume fuel".into(),
+      ));
+    }
+    if options.timeout.is_some() && !self.interruptable {
+      return Err(SycError::Link(
+        "a timeout needs an engine that is configured to be interruptable".into(),
+      ));
+    }
+    let mut ctx = SycContext::from_section(&program.context)?;
+    // The policy inside the module only limits what the program can do,
+    // access to the host's files and environment comes from the host
+    let policy = options
+      .sandbox
+      .unwrap_or_else(|| ctx.sandbox.clone().without_grants())
+      .merge(&options.sandbox_overrides);
+
+    // Anything the policy doesn't allow is left out, which leaves the program
+    // with no arguments, no environment and empty standard streams
+    let mut builder = WasiCtxBuilder::new();
+    if policy.allows(Capability::Args) {
+      builder = builder
+        .args(&options.args)
+        .map_err(|e| SycError::Link(e.into()))?;
+    }
+    let env: Vec<(String, String)> = options
+      .env
+      .into_iter()
+      .filter(|(name, _)| policy.allows_env(name))
+      .collect();
+    builder = builder.envs(&env).map_err(|e| SycError::Link(e.into()))?;
+    if policy.allows(Capability::Stdin) {
+      builder = match options.stdin {
+        InputPipe::Inherit => builder.inherit_stdin(),
+        InputPipe::Bytes(bytes) => builder.stdin(Box::new(ReadPipe::from(bytes))),
+        InputPipe::Reader(reader) => builder.stdin(Box::new(ReadPipe::new(reader))),
+      };
+    }
+    let mut captured_stdout = None;
+    if policy.allows(Capability::Stdout) {
+      let (stdout, captured) = output_pipe(options.stdout);
+      builder = match stdout {
+        Some(pipe) => builder.stdout(pipe),
+        None => builder.inherit_stdout(),
+      };
+      captured_stdout = captured;
+    }
+    let mut captured_stderr = None;
+    if policy.allows(Capability::Stderr) {
+      let (stderr, captured) = output_pipe(options.stderr);
+      builder = match stderr {
+        Some(pipe) => builder.stderr(pipe),
+        None => builder.inherit_stderr(),
+      };
+      captured_stderr = captured;
+    }
+    let mut wasi = builder.build();
+    for dir in &policy.dirs {
+      let host = Dir::open_ambient_dir(&dir.host, ambient_authority())?;
+      let (caps, file_caps) = dir.access.caps();
+      wasi
+        .push_dir(
+          Box::new(SyncDir::from_cap_std(host)),
+          caps,
+          file_caps,
+          PathBuf::from(&dir.guest),
+        )
+        .map_err(|e| SycError::Link(e.into()))?;
+    }
+
+    ctx.wasi = Some(wasi);
+    ctx.limits = policy.limits();
+    let mut store = Store::new(&self.engine, ctx);
+    store.limiter(|ctx| &mut ctx.limits);
+    // An engine that meters fuel can't run anything without it, so without a
+    // limit there is enough to run for a very long time
+    if self.fuel {
+      store
+        .add_fuel(options.fuel.unwrap_or(i64::MAX as u64))
+        .map_err(|e| SycError::Link(e.into()))?;
+    }
+    let timer = match options.timeout {
+      Some(timeout) => Some(start_timer(&store, timeout)?),
+      None => None,
+    };
+    let result = self.call_start(&mut store, &program.module);
+    // Stop the timer now that the program is done
+    drop(timer);
+    let exit_code = match result {
+      Err(SycError::Trap(trap)) => {
+        return Err(limit_error(trap, &store, options.fuel, options.timeout))
+      }
+      result => result?,
+    };
+    // The pipes are shared with the store so it has to go before they can be
+    // read
+    drop(store);
+    Ok(RunOutput {
+      exit_code,
+      stdout: captured_stdout.map_or_else(Vec::new, into_bytes),
+      stderr: captured_stderr.map_or_else(Vec::new, into_bytes),
+    })
+  }
+
+  /// Instantiate a module and call its `_start` function, returning the exit
+  /// code of the program
+  fn call_start(&self, store: &mut Store<SycContext>, module: &Module) -> Result<i32, SycError> {
+    let instance = self
+      .linker
+      .instantiate(&mut *store, module)
+      .map_err(|e| SycError::Link(e.into()))?;
+    let main = instance
+      .get_typed_func::<(), (), _>(&mut *store, "_start")
+      .map_err(|e| SycError::Link(e.into()))?;
+    match main.call(&mut *store, ()) {
+      Ok(()) => Ok(0),
+      // `terminate` exits through `proc_exit` which shows up as a trap
+      Err(trap) => match trap.i32_exit_status() {
+        Some(code) => Ok(code),
+        None => Err(SycError::Trap(trap)),
+      },
+    }
+  }
+}
+
+/// Interrupt the program running in the store once `timeout` has passed
+/// unless the returned `Sender` is dropped before then
+fn start_timer(store: &Store<SycContext>, timeout: Duration) -> Result<mpsc::Sender<()>, SycError> {
+  let handle = store
+    .interrupt_handle()
+    .map_err(|e| SycError::Link(e.into()))?;
+  let (timer, done) = mpsc::channel();
+  thread::spawn(move || {
+    if let Err(RecvTimeoutError::Timeout) = done.recv_timeout(timeout) {
+      handle.interrupt();
+    }
+  });
+  Ok(timer)
+}
+
+/// Turn a trap caused by the program going over one of its limits into an
+/// error that says which limit it was and which state was running
+fn limit_error(
+  trap: Trap,
+  store: &Store<SycContext>,
+  fuel: Option<u64>,
+  timeout: Option<Duration>,
+) -> SycError {
+  let state = running_state(&trap);
+  match (timeout, fuel) {
+    (Some(timeout), _) if trap.trap_code() == Some(TrapCode::Interrupt) => {
+      SycError::Timeout { timeout, state }
+    }
+    (_, Some(fuel)) if store.fuel_consumed().map_or(false, |used| used >= fuel) => {
+      SycError::OutOfFuel { fuel, state }
+    }
+    _ => SycError::Trap(trap),
+  }
+}
+
+/// Find the state that was running when a trap happened from the innermost
+/// function in its backtrace that is not part of the runtime
+fn running_state(trap: &Trap) -> Option<String> {
+  trap
+    .trace()
+    .iter()
+    .filter_map(|frame| frame.func_name())
+    .find(|name| *name != "_start" && !name.starts_with("__"))
+    .map(String::from)
+}
+
+type Captured = WritePipe<Cursor<Vec<u8>>>;
+
+/// Create the WASI file for an `OutputPipe` along with the buffer it writes
+/// into when it is captured. There is no file when the host stream is
+/// inherited.
+fn output_pipe(pipe: OutputPipe) -> (Option<Box<dyn wasmtime_wasi::WasiFile>>, Option<Captured>) {
+  match pipe {
+    OutputPipe::Inherit => (None, None),
+    OutputPipe::Capture => {
+      let captured = WritePipe::new_in_memory();
+      (Some(Box::new(captured.clone())), Some(captured))
+    }
+    OutputPipe::Writer(writer) => (Some(Box::new(WritePipe::new(writer))), None),
+  }
+}
+
+/// Get everything written into a captured pipe
+fn into_bytes(captured: Captured) -> Vec<u8> {
+  captured
+    .try_into_inner()
+    .map(Cursor::into_inner)
+    .unwrap_or_default()
+}
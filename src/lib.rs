@@ -1,49 +1,175 @@
+mod builtins;
 mod codegen;
+mod compiler;
 mod context;
+mod diagnostics;
+mod doc;
+mod emit;
+mod error;
+mod graph;
+mod host;
+mod memory;
 mod parser;
+mod resolve;
+mod sandbox;
+mod typeck;
 mod types;
+mod validate;
 mod wasi;
 
-use crate::{codegen::Codegen, context::SycContext, parser::SycParser, wasi::wasi_linker};
-use std::{error::Error, fs, path::PathBuf};
-use wasmtime::*;
-use wasmtime_wasi::sync::WasiCtxBuilder;
+pub use crate::{
+  compiler::{CompileOptions, CompiledModule, Compiler, Target},
+  diagnostics::{Diagnostic, Diagnostics, Level, SpanLabel},
+  emit::{EmitKind, Output},
+  error::{ErrorCode, Phase, SycError},
+  graph::GraphFormat,
+  host::{InputPipe, OutputPipe, Program, RunOptions, RunOutput, SycRuntime},
+  sandbox::{Capability, DirAccess, PreopenedDir, SandboxOverrides, SandboxPolicy},
+  types::{BinOp, Expr, Ident, Param, Statement, StrLit, SycValue, Type, UnaryOp},
+  wasi::{PrintArg, Wasi},
+};
 
-/// Build a sycamore program from a given file path
-pub fn build(path: &mut PathBuf, debug: bool) -> Result<Vec<u8>, Box<dyn Error>> {
-  let input = fs::read_to_string(&path)?;
-  if debug {
-    println!("------------------ Sycamore Input ------------------");
-    println!("{}", input);
+use crate::{graph::StateGraph, parser::SycParser};
+use std::{fs, path::Path};
+
+/// Build a sycamore program from a given file path, writing each of the
+/// given kinds of output to where `output` says. A `.wat` file is compiled as
+/// WebAssembly text instead, so that a module emitted as `wat` can be edited
+/// by hand and built again. Any problems found in the program are reported to
+/// stderr and returned in the `SycError`.
+pub fn build(
+  path: &Path,
+  debug: bool,
+  emit: &[EmitKind],
+  output: &Output,
+) -> Result<Vec<u8>, SycError> {
+  let input = fs::read_to_string(path)?;
+  let write = |kind: EmitKind, contents: &[u8]| -> Result<(), SycError> {
+    let out = output.path_for(path, kind, emit.len());
+    if let Some(dir) = out.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+      fs::create_dir_all(dir)?;
+    }
+    fs::write(out, contents)?;
+    Ok(())
+  };
+
+  if path.extension().map_or(false, |ext| ext == "wat") {
+    if let Some(kind) = emit
+      .iter()
+      .find(|kind| matches!(kind, EmitKind::Tokens | EmitKind::Ast))
+    {
+      return Err(SycError::Wat(format!(
+        "there are no {} to emit for WebAssembly text",
+        kind
+      )));
+    }
+    let cwasm = emit::wat_to_wasm(&input)?;
+    eprintln!(
+      "warning: `{}` is built with the default sandbox, the sandbox of the \
+       module it was emitted from is not kept in WebAssembly text",
+      path.display()
+    );
+    // The text that was just compiled is not written over
+    if emit.contains(&EmitKind::Wasm) {
+      write(EmitKind::Wasm, &cwasm)?;
+    }
+    return Ok(cwasm);
+  }
+
+  // The tokens are written before compiling so that they are there to look at
+  // when the program doesn't compile
+  if emit.contains(&EmitKind::Tokens) {
+    write(EmitKind::Tokens, emit::tokens(&input).as_bytes())?;
+  }
+  let compiler = Compiler::new(CompileOptions {
+    debug,
+    emit: emit.to_vec(),
+    ..CompileOptions::default()
+  });
+  let module = report(compiler.compile_str(&input, &path.display().to_string()))?;
+  for kind in emit {
+    match (kind, module.output(*kind)) {
+      (EmitKind::Tokens, _) | (_, None) => (),
+      (_, Some(contents)) => write(*kind, contents)?,
+    }
   }
-  let parsed = SycParser::new(&input).parse();
-  let cwasm = Codegen::new(parsed, debug).generate();
-  path.set_extension("csm");
-  fs::write(path.file_name().unwrap(), &cwasm)?;
-  Ok(cwasm)
+  Ok(module.wasm)
 }
 
-/// Run a sycamore program given a valid input of bytes
-pub fn run(csm: Vec<u8>, debug: bool) -> Result<(), Box<dyn Error>> {
-  if debug {
-    println!("------------------ Code Execution ------------------");
+/// Compile sycamore source code into a module without reading or writing any
+/// files. `file_name` is only used to label the problems found in the
+/// program, which are reported to stderr and returned in the `SycError`. Use a
+/// [`Compiler`] to get at the problems instead.
+pub fn compile(input: &str, file_name: &str, debug: bool) -> Result<Vec<u8>, SycError> {
+  let compiler = Compiler::new(CompileOptions {
+    debug,
+    ..CompileOptions::default()
+  });
+  report(compiler.compile_str(input, file_name)).map(|module| module.wasm)
+}
+
+/// Report the problems found while compiling a program to stderr
+fn report(result: Result<CompiledModule, Diagnostics>) -> Result<CompiledModule, SycError> {
+  match result {
+    Ok(module) => {
+      module.warnings.report()?;
+      Ok(module)
+    }
+    Err(diagnostics) => {
+      diagnostics.report()?;
+      Err(diagnostics.into())
+    }
   }
-  let engine = Engine::default();
-  let module = Module::new(&engine, &csm)?;
-  let mut linker = Linker::new(&engine);
-  wasi_linker(&mut linker)?;
-  let mut ctx = SycContext::from_sycamore_binary(&csm);
-  ctx.wasi = Some(
-    WasiCtxBuilder::new()
-      .inherit_stdio()
-      .inherit_args()?
-      .build(),
+}
+
+/// Export the state machine of the sycamore program at the given file path in
+/// the given format
+pub fn graph(path: &Path, format: GraphFormat) -> Result<String, SycError> {
+  let parsed = parse_file(path)?;
+  Ok(StateGraph::new(&parsed).render(format))
+}
+
+/// Generate Markdown documentation for the sycamore program at the given file
+/// path from the `///` comments on its states. The file name is used as the
+/// title.
+pub fn doc(path: &Path) -> Result<String, SycError> {
+  let parsed = parse_file(path)?;
+  let title = path.file_stem().map_or_else(
+    || path.display().to_string(),
+    |stem| stem.to_string_lossy().into_owned(),
   );
+  Ok(doc::render_markdown(&title, &parsed))
+}
 
-  let mut store = Store::new(&engine, ctx);
-  let instance = linker.instantiate(&mut store, &module)?;
-  let main = instance.get_typed_func::<(), (), _>(&mut store, "_start")?;
-  main.call(&mut store, ())?;
+/// Read and parse the sycamore program at the given file path. Syntax errors
+/// and warnings are reported to stderr and the errors are returned in the
+/// `SycError`.
+fn parse_file(path: &Path) -> Result<Vec<Statement>, SycError> {
+  let input = fs::read_to_string(path)?;
+  let (parsed, diagnostics) = SycParser::new(&input).parse();
+  let file_name = path.display().to_string();
+  for diagnostic in &diagnostics {
+    diagnostic.report(&file_name, &input)?;
+  }
+  if diagnostics.iter().any(Diagnostic::is_error) {
+    return Err(SycError::from_diagnostics(diagnostics));
+  }
+  Ok(parsed)
+}
 
-  Ok(())
+/// Run a sycamore program given a valid input of bytes in the host
+/// environment and with the limits described by `options`. Returns the exit
+/// code the program ended with, which is 0 unless it used `terminate` with a
+/// code.
+pub fn run(csm: Vec<u8>, debug: bool, options: RunOptions) -> Result<i32, SycError> {
+  if debug {
+    println!("------------------ Code Execution ------------------");
+  }
+  // Only pay for keeping track of limits when there are any
+  let runtime = if options.fuel.is_some() || options.timeout.is_some() {
+    SycRuntime::with_limits()?
+  } else {
+    SycRuntime::new()?
+  };
+  runtime.run(&csm, options).map(|output| output.exit_code)
 }
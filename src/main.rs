@@ -1,6 +1,9 @@
 use clap::Parser;
-use libsyc::{build, run};
-use std::{error::Error, path::PathBuf};
+use libsyc::{
+  build, doc, graph, run, Capability, DirAccess, EmitKind, GraphFormat, Output, PreopenedDir,
+  RunOptions, SandboxOverrides, SycError,
+};
+use std::{env, fs, path::PathBuf, process, time::Duration};
 
 /// This doc string acts as a help message when the user runs '--help'
 /// as do all doc strings on fields
@@ -19,8 +22,37 @@ struct Opts {
 #[derive(Parser)]
 enum SubCommand {
   /// Compile sycamore code to wasm
-  Build { path: PathBuf },
-  /// Compile sycamore code to wasm and run it
+  Build {
+    /// Path to the sycamore source code, or WebAssembly text to compile
+    path: PathBuf,
+    #[clap(long, default_value = "wasm", require_delimiter = true)]
+    /// What to write out, any of tokens, ast, wat or wasm separated by commas.
+    /// Each is written to its own file named after the program.
+    emit: Vec<EmitKind>,
+    #[clap(short, long)]
+    /// Write the output to this file instead of next to the source. When
+    /// more than one kind of output is emitted each gets its own extension.
+    output: Option<PathBuf>,
+    #[clap(long)]
+    /// Write the output into this directory instead of next to the source.
+    /// Ignored if `--output` is given.
+    out_dir: Option<PathBuf>,
+  },
+  /// Print the state machine of a sycamore program as a diagram
+  Graph {
+    /// Path to the sycamore source code
+    path: PathBuf,
+    #[clap(short, long, default_value = "dot")]
+    /// The format to print the diagram in: dot, mermaid or json
+    format: GraphFormat,
+  },
+  /// Print Markdown documentation for the states of a sycamore program
+  Doc {
+    /// Path to the sycamore source code
+    path: PathBuf,
+  },
+  /// Compile sycamore code to wasm and run it. The compiled module is
+  /// written next to the source as with `build`.
   Run {
     /// Path to the sycamore source code or compiled wasm module
     path: PathBuf,
@@ -31,26 +63,134 @@ enum SubCommand {
     #[clap(short, long)]
     /// The path given is a compiled sycamore wasm module that should be run
     wasm: bool,
+    #[clap(long)]
+    /// Stop the program once it has used this much fuel. Every instruction
+    /// uses up some fuel.
+    fuel: Option<u64>,
+    #[clap(long, parse(try_from_str = parse_duration))]
+    /// Stop the program once it has run for this long, e.g. 500ms, 5s or 2m
+    timeout: Option<Duration>,
+    #[clap(long)]
+    /// The most 64KiB pages of memory the program can use. This and the other
+    /// sandbox options replace that part of the sandbox the program was
+    /// compiled with.
+    max_memory_pages: Option<u32>,
+    #[clap(long, require_delimiter = true)]
+    /// The only things the program can do, any of stdin, stdout, stderr,
+    /// args or env separated by commas
+    allow: Option<Vec<Capability>>,
+    #[clap(long, number_of_values = 1)]
+    /// Let the program read a directory, given as HOST or HOST:GUEST
+    dir: Vec<String>,
+    #[clap(long, number_of_values = 1)]
+    /// Let the program read and write a directory, given as HOST or
+    /// HOST:GUEST
+    dir_rw: Vec<String>,
+    #[clap(long = "env", number_of_values = 1)]
+    /// Let the program see this environment variable of this process.
+    /// Without it the program sees no environment variables.
+    env_vars: Vec<String>,
   },
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
+fn main() {
   let opts = Opts::parse();
 
-  match opts.subcmd {
-    SubCommand::Build { mut path } => build(&mut path, false).map(drop),
+  let result = match opts.subcmd {
+    SubCommand::Build {
+      path,
+      emit,
+      output,
+      out_dir,
+    } => {
+      let output = match (output, out_dir) {
+        (Some(file), _) => Output::File(file),
+        (None, Some(dir)) => Output::Dir(dir),
+        (None, None) => Output::NextToSource,
+      };
+      build(&path, false, &emit, &output).map(|_| 0)
+    }
+    SubCommand::Graph { path, format } => graph(&path, format).map(|diagram| {
+      print!("{}", diagram);
+      0
+    }),
+    SubCommand::Doc { path } => doc(&path).map(|docs| {
+      print!("{}", docs);
+      0
+    }),
     SubCommand::Run {
-      mut path,
+      path,
       debug,
       wasm,
+      fuel,
+      timeout,
+      max_memory_pages,
+      allow,
+      dir,
+      dir_rw,
+      env_vars,
     } => {
+      // Only the parts of the sandbox that were given replace the ones the
+      // program was compiled with
+      let dirs = dir
+        .iter()
+        .map(|dir| PreopenedDir::parse(dir, DirAccess::ReadOnly))
+        .chain(
+          dir_rw
+            .iter()
+            .map(|dir| PreopenedDir::parse(dir, DirAccess::ReadWrite)),
+        )
+        .collect::<Vec<_>>();
+      let options = RunOptions {
+        args: env::args().collect(),
+        env: env_vars
+          .iter()
+          .filter_map(|name| env::var(name).ok().map(|value| (name.clone(), value)))
+          .collect(),
+        fuel,
+        timeout,
+        sandbox_overrides: SandboxOverrides {
+          max_memory_pages,
+          capabilities: allow,
+          dirs: (!dirs.is_empty()).then(|| dirs),
+          env_allowlist: (!env_vars.is_empty()).then(|| env_vars),
+        },
+        ..RunOptions::default()
+      };
       if !wasm {
-        let cwasm = build(&mut path, debug)?;
-        run(cwasm, debug)
+        build(&path, debug, &[EmitKind::Wasm], &Output::NextToSource)
+          .and_then(|cwasm| run(cwasm, debug, options))
       } else {
-        let cwasm = std::fs::read(path)?;
-        run(cwasm, debug)
+        fs::read(path)
+          .map_err(SycError::from)
+          .and_then(|cwasm| run(cwasm, debug, options))
       }
     }
+  };
+
+  match result {
+    Ok(code) => process::exit(code),
+    Err(e) => {
+      eprintln!("error: {}", e);
+      process::exit(1);
+    }
+  }
+}
+
+/// Parse a duration made of a number and a unit of ms, s or m
+fn parse_duration(s: &str) -> Result<Duration, String> {
+  let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+  let (amount, unit) = s.split_at(split);
+  let amount: u64 = amount
+    .parse()
+    .map_err(|_| format!("`{}` does not start with a number", s))?;
+  match unit {
+    "ms" => Ok(Duration::from_millis(amount)),
+    "s" => Ok(Duration::from_secs(amount)),
+    "m" => amount
+      .checked_mul(60)
+      .map(Duration::from_secs)
+      .ok_or_else(|| format!("`{}` is too long a duration", s)),
+    _ => Err(format!("unknown unit `{}`, expected ms, s or m", unit)),
   }
 }
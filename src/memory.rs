@@ -0,0 +1,251 @@
+use crate::{builtins::Builtin, codegen::Codegen};
+use wasm_encoder::*;
+
+/// The size of a page of wasm memory
+pub const PAGE_SIZE: i32 = 65536;
+
+/// Memory is laid out as the stack, then static data, then the heap. The
+/// stack takes up the first page and grows down towards 0, so running out of
+/// stack traps instead of overwriting any data.
+pub const STACK_SIZE: i32 = PAGE_SIZE;
+
+/// Name of the global pointing at the top of the stack
+pub const STACK_POINTER: &str = "__stack_pointer";
+/// Name of the global pointing at the end of the heap
+pub const HEAP_END: &str = "__heap_end";
+/// Name of the global pointing at the first block in the free list
+pub const FREE_LIST: &str = "__free_list";
+
+/// Every heap block starts with its size followed by the next block in the
+/// free list while it is free
+const BLOCK_HEADER: i32 = 8;
+
+/// Management of the linear memory of a sycamore program. Temporary values
+/// that only live for a single statement get allocated on the stack, anything
+/// else lives on the heap. The heap is managed by the `Alloc` and `Free`
+/// builtins. Strings made while evaluating an expression are freed by
+/// whatever uses them up, strings that are stored in a variable are kept.
+pub trait Memory {
+  fn register_memory(&mut self);
+  fn init_memory(&mut self);
+  fn stack_alloc(&mut self, size: i32);
+  fn stack_free(&mut self, size: i32);
+}
+
+impl Memory for Codegen {
+  /// Register the globals used to manage memory
+  fn register_memory(&mut self) {
+    self.add_global(
+      STACK_POINTER,
+      ValType::I32,
+      &Instruction::I32Const(STACK_SIZE),
+    );
+    // The heap starts after the static data which is only known once all of
+    // the code is generated, so `_start` sets this
+    self.add_global(HEAP_END, ValType::I32, &Instruction::I32Const(0));
+    self.add_global(FREE_LIST, ValType::I32, &Instruction::I32Const(0));
+  }
+
+  /// Create instructions to set up the heap when the program starts
+  fn init_memory(&mut self) {
+    self.instruction(Instruction::I32Const(self.heap_start()));
+    self.instruction(Instruction::GlobalSet(self.global_map[HEAP_END]));
+  }
+
+  /// Create instructions to move the stack pointer down by `size` bytes,
+  /// trapping if the stack runs out. The allocated memory starts at the
+  /// stack pointer.
+  fn stack_alloc(&mut self, size: i32) {
+    let stack_pointer = self.global_map[STACK_POINTER];
+    self.instruction(Instruction::GlobalGet(stack_pointer));
+    self.instruction(Instruction::I32Const(size));
+    self.instruction(Instruction::I32LtU);
+    self.instruction(Instruction::If(BlockType::Empty));
+    self.instruction(Instruction::Unreachable);
+    self.instruction(Instruction::End);
+    self.instruction(Instruction::GlobalGet(stack_pointer));
+    self.instruction(Instruction::I32Const(size));
+    self.instruction(Instruction::I32Sub);
+    self.instruction(Instruction::GlobalSet(stack_pointer));
+  }
+
+  /// Create instructions to free the last `size` bytes allocated on the stack
+  fn stack_free(&mut self, size: i32) {
+    let stack_pointer = self.global_map[STACK_POINTER];
+    self.instruction(Instruction::GlobalGet(stack_pointer));
+    self.instruction(Instruction::I32Const(size));
+    self.instruction(Instruction::I32Add);
+    self.instruction(Instruction::GlobalSet(stack_pointer));
+  }
+}
+
+impl Codegen {
+  /// Where the heap starts, right after the static data
+  pub fn heap_start(&self) -> i32 {
+    align(STACK_SIZE + self.static_data.len() as i32, BLOCK_HEADER)
+  }
+
+  /// Generate `__alloc(size) -> ptr` which first looks for a free block that
+  /// is big enough and otherwise takes a new block from the end of the heap,
+  /// growing memory when it runs out
+  pub fn generate_alloc(&mut self) {
+    let idx = self.fn_map[Builtin::Alloc.name()];
+    self.functions.function(idx);
+    let heap_end = self.global_map[HEAP_END];
+    let free_list = self.global_map[FREE_LIST];
+
+    let (size, prev, block, next, end, pages) = (0, 1, 2, 3, 4, 5);
+    self.current_func = Some(Function::new_with_locals_types(vec![ValType::I32; 5]));
+    let header = mem_arg(0);
+    let next_field = mem_arg(4);
+
+    // Keep every block aligned
+    self.instruction(Instruction::LocalGet(size));
+    self.instruction(Instruction::I32Const(BLOCK_HEADER - 1));
+    self.instruction(Instruction::I32Add);
+    self.instruction(Instruction::I32Const(-BLOCK_HEADER));
+    self.instruction(Instruction::I32And);
+    self.instruction(Instruction::LocalSet(size));
+
+    // Use the first free block that is big enough
+    self.instruction(Instruction::I32Const(0));
+    self.instruction(Instruction::LocalSet(prev));
+    self.instruction(Instruction::GlobalGet(free_list));
+    self.instruction(Instruction::LocalSet(block));
+    self.instruction(Instruction::Block(BlockType::Empty));
+    self.instruction(Instruction::Loop(BlockType::Empty));
+    self.instruction(Instruction::LocalGet(block));
+    self.instruction(Instruction::I32Eqz);
+    self.instruction(Instruction::BrIf(1));
+    self.instruction(Instruction::LocalGet(block));
+    self.instruction(Instruction::I32Load(header));
+    self.instruction(Instruction::LocalGet(size));
+    self.instruction(Instruction::I32GeU);
+    self.instruction(Instruction::If(BlockType::Empty));
+    // Take the block out of the free list
+    self.instruction(Instruction::LocalGet(block));
+    self.instruction(Instruction::I32Load(next_field));
+    self.instruction(Instruction::LocalSet(next));
+    self.instruction(Instruction::LocalGet(prev));
+    self.instruction(Instruction::I32Eqz);
+    self.instruction(Instruction::If(BlockType::Empty));
+    self.instruction(Instruction::LocalGet(next));
+    self.instruction(Instruction::GlobalSet(free_list));
+    self.instruction(Instruction::Else);
+    self.instruction(Instruction::LocalGet(prev));
+    self.instruction(Instruction::LocalGet(next));
+    self.instruction(Instruction::I32Store(next_field));
+    self.instruction(Instruction::End);
+    self.instruction(Instruction::LocalGet(block));
+    self.instruction(Instruction::I32Const(BLOCK_HEADER));
+    self.instruction(Instruction::I32Add);
+    self.instruction(Instruction::Return);
+    self.instruction(Instruction::End);
+    self.instruction(Instruction::LocalGet(block));
+    self.instruction(Instruction::LocalSet(prev));
+    self.instruction(Instruction::LocalGet(block));
+    self.instruction(Instruction::I32Load(next_field));
+    self.instruction(Instruction::LocalSet(block));
+    self.instruction(Instruction::Br(0));
+    self.instruction(Instruction::End);
+    self.instruction(Instruction::End);
+
+    // Otherwise take a new block from the end of the heap
+    self.instruction(Instruction::GlobalGet(heap_end));
+    self.instruction(Instruction::LocalTee(block));
+    self.instruction(Instruction::I32Const(BLOCK_HEADER));
+    self.instruction(Instruction::I32Add);
+    self.instruction(Instruction::LocalGet(size));
+    self.instruction(Instruction::I32Add);
+    self.instruction(Instruction::LocalSet(end));
+    // Grow memory by enough pages to fit the block if it doesn't fit. This
+    // is worked out in pages since the size of a full 4GiB memory in bytes
+    // doesn't fit in an i32.
+    self.instruction(Instruction::LocalGet(end));
+    self.instruction(Instruction::I32Const(1));
+    self.instruction(Instruction::I32Sub);
+    self.instruction(Instruction::I32Const(16));
+    self.instruction(Instruction::I32ShrU);
+    self.instruction(Instruction::I32Const(1));
+    self.instruction(Instruction::I32Add);
+    self.instruction(Instruction::LocalTee(pages));
+    self.instruction(Instruction::MemorySize(0));
+    self.instruction(Instruction::I32GtU);
+    self.instruction(Instruction::If(BlockType::Empty));
+    self.instruction(Instruction::LocalGet(pages));
+    self.instruction(Instruction::MemorySize(0));
+    self.instruction(Instruction::I32Sub);
+    self.instruction(Instruction::MemoryGrow(0));
+    self.instruction(Instruction::I32Const(-1));
+    self.instruction(Instruction::I32Eq);
+    self.instruction(Instruction::If(BlockType::Empty));
+    self.instruction(Instruction::Unreachable);
+    self.instruction(Instruction::End);
+    self.instruction(Instruction::End);
+    self.instruction(Instruction::LocalGet(block));
+    self.instruction(Instruction::LocalGet(size));
+    self.instruction(Instruction::I32Store(header));
+    self.instruction(Instruction::LocalGet(end));
+    self.instruction(Instruction::GlobalSet(heap_end));
+    self.instruction(Instruction::LocalGet(block));
+    self.instruction(Instruction::I32Const(BLOCK_HEADER));
+    self.instruction(Instruction::I32Add);
+    self.instruction(Instruction::End);
+
+    let mut local_names = NameMap::new();
+    local_names.append(size, "size");
+    local_names.append(prev, "prev");
+    local_names.append(block, "block");
+    local_names.append(next, "next");
+    local_names.append(end, "end");
+    local_names.append(pages, "pages");
+    self.name.local_names.append(idx, &local_names);
+    self.codes.function(&self.current_func.take().unwrap());
+  }
+
+  /// Generate `__free(ptr)` which puts the block at the front of the free list
+  pub fn generate_free(&mut self) {
+    let idx = self.fn_map[Builtin::Free.name()];
+    self.functions.function(idx);
+    let free_list = self.global_map[FREE_LIST];
+
+    let (ptr, block) = (0, 1);
+    self.current_func = Some(Function::new_with_locals_types(vec![ValType::I32]));
+
+    // Freeing a null pointer does nothing
+    self.instruction(Instruction::LocalGet(ptr));
+    self.instruction(Instruction::I32Eqz);
+    self.instruction(Instruction::If(BlockType::Empty));
+    self.instruction(Instruction::Return);
+    self.instruction(Instruction::End);
+    self.instruction(Instruction::LocalGet(ptr));
+    self.instruction(Instruction::I32Const(BLOCK_HEADER));
+    self.instruction(Instruction::I32Sub);
+    self.instruction(Instruction::LocalTee(block));
+    self.instruction(Instruction::GlobalGet(free_list));
+    self.instruction(Instruction::I32Store(mem_arg(4)));
+    self.instruction(Instruction::LocalGet(block));
+    self.instruction(Instruction::GlobalSet(free_list));
+    self.instruction(Instruction::End);
+
+    let mut local_names = NameMap::new();
+    local_names.append(ptr, "ptr");
+    local_names.append(block, "block");
+    self.name.local_names.append(idx, &local_names);
+    self.codes.function(&self.current_func.take().unwrap());
+  }
+}
+
+/// Round `value` up to the next multiple of `to`, which is a power of 2
+pub fn align(value: i32, to: i32) -> i32 {
+  (value + to - 1) & -to
+}
+
+/// Get a `MemArg` for accessing memory at `offset` bytes past an address
+pub fn mem_arg(offset: u32) -> MemArg {
+  MemArg {
+    memory_index: 0,
+    align: 0,
+    offset,
+  }
+}
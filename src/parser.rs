@@ -1,16 +1,27 @@
 use crate::{
-  types::{Ident, Statement, StrLit, SycValue, Type},
-  wasi::Wasi,
+  diagnostics::Diagnostic,
+  error::ErrorCode,
+  types::{BinOp, Expr, Ident, Param, Statement, StrLit, SycValue, Type, UnaryOp},
+  wasi::{PrintArg, Wasi},
 };
 use logos::{Logos, SpannedIter};
-use std::{iter::Peekable, ops::Range};
+use std::{fmt, iter::Peekable, ops::Range};
+
+/// The result of parsing part of a sycamore program
+pub type ParseResult<T> = Result<T, Diagnostic>;
 
 /// A parser for a sycamore program. Given an input it will generate a
-/// `Vec<Statement>` for use in a `Codegen` to create the output of the program
+/// `Vec<Statement>` for use in a `Codegen` to create the output of the program.
+/// The parser does not stop at the first syntax error. It records it, skips
+/// ahead to the next statement or state and keeps going so that every error in
+/// the program can be reported at once.
 pub struct SycParser<'lex> {
   input: &'lex str,
+  /// Where the part of the input being parsed ends
+  end: usize,
   lex: Peekable<SpannedIter<'lex, Token>>,
   current: Option<(Token, Range<usize>)>,
+  errors: Vec<Diagnostic>,
 }
 
 impl<'lex> SycParser<'lex> {
@@ -19,66 +30,377 @@ impl<'lex> SycParser<'lex> {
     let lex = Token::lexer(input).spanned().peekable();
     Self {
       input,
+      end: input.len(),
       lex,
       current: None,
+      errors: Vec::new(),
+    }
+  }
+  /// Create a `SycParser` that only parses the given range of the input.
+  /// Spans are still relative to the start of the whole input.
+  pub fn new_in(input: &'lex str, range: Range<usize>) -> Self {
+    let mut lex = Token::lexer(&input[..range.end]);
+    lex.bump(range.start);
+    Self {
+      input,
+      end: range.end,
+      lex: lex.spanned().peekable(),
+      current: None,
+      errors: Vec::new(),
     }
   }
+
   /// Get the slice of the `&str` for the current token
   pub fn slice(&self) -> &'lex str {
-    &self.input[self.current.as_ref().unwrap().1.clone()]
+    &self.input[self.span()]
   }
 
-  /// Assume we get the next token and consume it. Panic with the given error
-  /// string if this is not the case.
-  pub fn expect(&mut self, t: Token, err: &str) {
-    if self.next() != t {
-      panic!("{}", err);
+  /// Get the span of the current token. If no token has been consumed yet or
+  /// we are at the end of the input this points at the end of the input.
+  pub fn span(&self) -> Range<usize> {
+    match &self.current {
+      Some((_, span)) => span.clone(),
+      None => self.eof_span(),
+    }
+  }
+
+  /// Get the span at the end of the input
+  pub fn eof_span(&self) -> Range<usize> {
+    self.end..self.end
+  }
+
+  /// Assume the next token is the given one and consume it. Return a
+  /// `Diagnostic` with the given error string if this is not the case. The
+  /// unexpected token is left in place so that the parser can recover from it.
+  pub fn expect(&mut self, t: Token, err: &str) -> ParseResult<()> {
+    match self.lex.peek().cloned() {
+      Some((found, _)) if found == t => {
+        self.next()?;
+        Ok(())
+      }
+      Some((found, span)) => Err(
+        Diagnostic::error(ErrorCode::UnexpectedToken, err, span.clone())
+          .with_label(span, format!("expected {}, found {}", t, found)),
+      ),
+      None => Err(
+        Diagnostic::error(ErrorCode::UnexpectedEof, err, self.eof_span()).with_label(
+          self.eof_span(),
+          format!("expected {}, found end of file", t),
+        ),
+      ),
     }
   }
 
   /// Get the next `Token` to operate on
-  pub fn next(&mut self) -> Token {
+  pub fn next(&mut self) -> ParseResult<Token> {
     match self.lex.next() {
       Some((t, r)) => {
         self.current = Some((t, r));
-        t
+        Ok(t)
+      }
+      None => {
+        self.current = None;
+        Err(
+          Diagnostic::error(
+            ErrorCode::UnexpectedEof,
+            "unexpected end of file",
+            self.span(),
+          )
+          .with_label(self.span(), "the file ended here"),
+        )
       }
-      None => panic!("Hit EOF"),
     }
   }
 
   /// Check if the next `Token` is equal to the given `Token`
   pub fn peek(&mut self, tok: Token) -> bool {
-    self.lex.peek().map(|(t, _)| t) == Some(&tok)
+    self.peek_token() == Some(tok)
+  }
+
+  /// Get the next `Token` without consuming it
+  pub fn peek_token(&mut self) -> Option<Token> {
+    self.lex.peek().map(|(t, _)| *t)
+  }
+
+  /// Skip ahead to the start of the next statement after a syntax error. This
+  /// consumes everything up to and including the next `;`, but stops before a
+  /// `}` or the start of a new state so the enclosing block or program can
+  /// carry on from there. A block in the way is skipped as a whole, along
+  /// with the `;`s inside of it.
+  pub fn synchronize_statement(&mut self) {
+    let mut depth = 0;
+    loop {
+      match self.peek_token() {
+        None | Some(Token::State) | Some(Token::Terminating) => break,
+        Some(Token::RCurly) if depth == 0 => break,
+        Some(Token::RCurly) => {
+          self.next_opt();
+          depth -= 1;
+          // The block was the end of the broken statement, like in `if`
+          if depth == 0 {
+            break;
+          }
+        }
+        Some(Token::LCurly) => {
+          self.next_opt();
+          depth += 1;
+        }
+        Some(Token::SemiColon) if depth == 0 => {
+          self.next_opt();
+          break;
+        }
+        Some(_) => {
+          self.next_opt();
+        }
+      }
+    }
+  }
+
+  /// Skip ahead to the start of the next state after a syntax error
+  pub fn synchronize_state(&mut self) {
+    while !matches!(
+      self.peek_token(),
+      None | Some(Token::State) | Some(Token::Terminating) | Some(Token::DocComment)
+    ) {
+      self.next_opt();
+    }
   }
 
   /// Create an `Ident`
-  pub fn ident(&mut self) -> Ident {
-    self.expect(Token::Identifier, "No ident token");
-    self.mk_ident()
+  pub fn ident(&mut self) -> ParseResult<Ident> {
+    self.expect(Token::Identifier, "Expected an identifier")?;
+    Ok(self.mk_ident())
   }
 
   /// Make an `Ident` from the given slice
   pub fn mk_ident(&mut self) -> Ident {
-    Ident::new(self.slice())
+    Ident::new(self.slice(), self.span())
   }
 
-  /// Make a number from the given token
-  pub fn mk_number(&mut self) -> SycValue {
-    SycValue::I32(self.slice().parse::<i32>().expect("An i32 number"))
+  /// Make a number from the given token, which is negated if `negative` is
+  /// set. The sign is part of the number so that `-2147483648` is the
+  /// smallest i32 rather than a negated i64.
+  pub fn mk_number(&mut self, negative: bool) -> ParseResult<SycValue> {
+    // Numbers are an i32 unless they are too big to be one
+    let sign = if negative { "-" } else { "" };
+    match format!("{}{}", sign, self.slice()).parse::<i64>() {
+      Ok(num) => Ok(
+        i32::try_from(num)
+          .map(SycValue::I32)
+          .unwrap_or(SycValue::I64(num)),
+      ),
+      Err(e) => Err(
+        Diagnostic::error(ErrorCode::InvalidNumber, "Invalid number", self.span())
+          .with_label(self.span(), format!("this is not a valid i64: {}", e)),
+      ),
+    }
+  }
+
+  /// Create a floating point `SycValue`, which is an f64 unless the type
+  /// checker finds an f32 is needed
+  pub fn mk_float(&mut self) -> ParseResult<SycValue> {
+    match self.slice().parse::<f64>() {
+      Ok(num) => Ok(SycValue::F64(num)),
+      Err(e) => Err(
+        Diagnostic::error(ErrorCode::InvalidNumber, "Invalid number", self.span())
+          .with_label(self.span(), format!("this is not a valid f64: {}", e)),
+      ),
+    }
   }
 
   /// Create a `StrLit`
-  pub fn string_literal(&mut self) -> StrLit {
-    self.expect(Token::StringLiteral, "No ident token");
+  pub fn string_literal(&mut self) -> ParseResult<StrLit> {
+    self.expect(Token::StringLiteral, "Expected a string literal")?;
     self.mk_str_lit()
   }
 
-  /// Make a `StrLit` from the given slice
-  pub fn mk_str_lit(&mut self) -> StrLit {
-    let slice = self.slice();
+  /// Make a `StrLit` from the given slice, replacing any escape sequences with
+  /// the characters they stand for
+  pub fn mk_str_lit(&mut self) -> ParseResult<StrLit> {
+    let span = self.span();
     // Get rid of the quotes here
-    StrLit::new(&slice[1..slice.len() - 1])
+    let start = span.start + 1;
+    let content = &self.input[start..span.end - 1];
+    let mut text = String::new();
+    let mut idx = 0;
+    while let Some(c) = content[idx..].chars().next() {
+      if c == '\\' {
+        let (c, len) = self.unescape(content, start, idx)?;
+        text.push(c);
+        idx += len;
+      } else {
+        text.push(c);
+        idx += c.len_utf8();
+      }
+    }
+    Ok(StrLit::new(text))
+  }
+
+  /// Read the escape sequence at `idx` in `content`, which starts at `offset`
+  /// in the input. Returns the character it stands for and how many bytes
+  /// long the escape sequence is.
+  fn unescape(&self, content: &str, offset: usize, idx: usize) -> ParseResult<(char, usize)> {
+    let rest = &content[idx + 1..];
+    let c = match rest.chars().next() {
+      Some('n') => '\n',
+      Some('t') => '\t',
+      Some('r') => '\r',
+      Some('0') => '\0',
+      Some('\\') => '\\',
+      Some('"') => '"',
+      Some('u') => return unescape_unicode(rest, offset + idx),
+      other => {
+        let len = 1 + other.map_or(0, char::len_utf8);
+        let span = offset + idx..offset + idx + len;
+        return Err(
+          Diagnostic::error(
+            ErrorCode::InvalidEscape,
+            format!("Unknown escape sequence `{}`", &content[idx..idx + len]),
+            span.clone(),
+          )
+          .with_label(span, "unknown escape sequence")
+          .with_note(r#"help: the valid escapes are \n \t \r \0 \\ \" and \u{...}"#),
+        );
+      }
+    };
+    Ok((c, 2))
+  }
+
+  /// Get the text of the current doc comment without the leading `///` and
+  /// the space after it
+  fn mk_doc_line(&self) -> String {
+    let line = &self.slice()[3..];
+    line
+      .strip_prefix(' ')
+      .unwrap_or(line)
+      .trim_end()
+      .to_string()
+  }
+
+  /// Parse what is given to a `print` or `println` statement. This is either a
+  /// string literal, which can have expressions in it wrapped in `{}`, or a
+  /// single expression.
+  pub fn parse_print_args(&mut self) -> ParseResult<Vec<PrintArg>> {
+    self.expect(Token::LParen, "No LParen for print statement")?;
+    let args = if self.peek(Token::StringLiteral) {
+      self.next()?;
+      // A string literal used in an expression is not interpolated
+      if self.peek_token().and_then(Token::as_binary_op).is_some() {
+        let lhs = Expr::Literal {
+          value: SycValue::Str(self.mk_str_lit()?),
+          span: self.span(),
+        };
+        vec![PrintArg::Expr(self.parse_binary(lhs, 0)?)]
+      } else {
+        self.interpolate()?
+      }
+    } else {
+      vec![PrintArg::Expr(self.parse_expr()?)]
+    };
+    self.expect(Token::RParen, "No RParen for print statement")?;
+    self.expect(Token::SemiColon, "No semicolon for print statement")?;
+    Ok(args)
+  }
+
+  /// Split the current string literal into the text and the expressions in
+  /// `{}` inside of it. A literal brace is written as `{{` or `}}`. Escape
+  /// sequences in the text are replaced with the characters they stand for.
+  pub fn interpolate(&mut self) -> ParseResult<Vec<PrintArg>> {
+    let span = self.span();
+    // Skip the quotes
+    let start = span.start + 1;
+    let content = &self.input[start..span.end - 1];
+    let mut args = Vec::new();
+    let mut text = String::new();
+    let mut idx = 0;
+    while let Some(c) = content[idx..].chars().next() {
+      let rest = &content[idx + c.len_utf8()..];
+      match c {
+        '{' | '}' if rest.starts_with(c) => {
+          text.push(c);
+          idx += 2;
+        }
+        '{' => {
+          let len = match rest.find('}') {
+            Some(len) => len,
+            None => {
+              let brace = start + idx..start + idx + 1;
+              return Err(
+                Diagnostic::error(
+                  ErrorCode::UnexpectedToken,
+                  "Unclosed `{` in string",
+                  brace.clone(),
+                )
+                .with_label(brace, "this `{` is never closed")
+                .with_note("help: use `{{` to print a `{`"),
+              );
+            }
+          };
+          let expr_start = start + idx + 1;
+          if !text.is_empty() {
+            args.push(PrintArg::Str(StrLit::new(&text)));
+            text.clear();
+          }
+          args.push(PrintArg::Expr(
+            self.parse_embedded_expr(expr_start..expr_start + len)?,
+          ));
+          idx += len + 2;
+        }
+        '}' => {
+          let brace = start + idx..start + idx + 1;
+          return Err(
+            Diagnostic::error(
+              ErrorCode::UnexpectedToken,
+              "Unmatched `}` in string",
+              brace.clone(),
+            )
+            .with_label(brace, "this `}` was never opened")
+            .with_note("help: use `}}` to print a `}`"),
+          );
+        }
+        '\\' => {
+          let (c, len) = self.unescape(content, start, idx)?;
+          text.push(c);
+          idx += len;
+        }
+        c => {
+          text.push(c);
+          idx += c.len_utf8();
+        }
+      }
+    }
+    if !text.is_empty() {
+      args.push(PrintArg::Str(StrLit::new(&text)));
+    }
+    Ok(args)
+  }
+
+  /// Parse the given range of the input as a single expression
+  fn parse_embedded_expr(&mut self, range: Range<usize>) -> ParseResult<Expr> {
+    if self.input[range.clone()].trim().is_empty() {
+      let span = range.start - 1..range.end + 1;
+      return Err(
+        Diagnostic::error(
+          ErrorCode::UnexpectedToken,
+          "Empty `{}` in string",
+          span.clone(),
+        )
+        .with_label(span, "expected an expression inside of the braces"),
+      );
+    }
+    let mut parser = SycParser::new_in(self.input, range);
+    let expr = parser.parse_expr()?;
+    match parser.lex.next() {
+      None => Ok(expr),
+      Some((t, span)) => Err(
+        Diagnostic::error(
+          ErrorCode::UnexpectedToken,
+          format!("Unexpected {}", t),
+          span.clone(),
+        )
+        .with_label(span, "expected the end of the expression"),
+      ),
+    }
   }
 
   /// Get the next token if it exists
@@ -87,95 +409,456 @@ impl<'lex> SycParser<'lex> {
     self.current.as_ref().map(|c| c.0)
   }
 
-  /// Parse args for a function call
-  pub fn parse_args(&mut self) -> Vec<Type> {
-    self.expect(Token::LParen, "No LParen token for args");
-    let args = Vec::new();
-    match self.next() {
-      Token::RParen => return args,
-      _ => unimplemented!("Args"),
+  /// Parse the parameters of a state definition, e.g. `(attempts: i32)`
+  pub fn parse_params(&mut self) -> ParseResult<Vec<Param>> {
+    self.expect(Token::LParen, "No LParen token for params")?;
+    let mut params = Vec::new();
+    if !self.peek(Token::RParen) {
+      loop {
+        let name = self.ident()?;
+        self.expect(Token::Colon, "No colon between the parameter and its type")?;
+        let ty = self.parse_type()?;
+        params.push(Param { name, ty });
+        if !self.peek(Token::Comma) {
+          break;
+        }
+        self.next()?;
+      }
+    }
+    self.expect(Token::RParen, "No RParen token for params")?;
+    Ok(params)
+  }
+
+  /// Parse the name of a type
+  pub fn parse_type(&mut self) -> ParseResult<Type> {
+    self.expect(Token::Identifier, "Expected a type")?;
+    match Type::from_name(self.slice()) {
+      Some(ty) => Ok(ty),
+      None => Err(
+        Diagnostic::error(
+          ErrorCode::UnknownType,
+          format!("Unknown type `{}`", self.slice()),
+          self.span(),
+        )
+        .with_label(self.span(), "this type does not exist")
+        .with_note("help: the available types are i32, i64, f32, f64, bool and string"),
+      ),
+    }
+  }
+
+  /// Parse args for a function call, e.g. `(attempts - 1)`
+  pub fn parse_args(&mut self) -> ParseResult<Vec<Expr>> {
+    self.expect(Token::LParen, "No LParen token for args")?;
+    let mut args = Vec::new();
+    if !self.peek(Token::RParen) {
+      loop {
+        args.push(self.parse_expr()?);
+        if !self.peek(Token::Comma) {
+          break;
+        }
+        self.next()?;
+      }
     }
+    self.expect(Token::RParen, "No RParen token for args")?;
+    Ok(args)
   }
-  /// Parse a block of statements
-  pub fn parse_block(&mut self) -> Vec<Statement> {
+
+  /// Parse a block of statements. Syntax errors inside of the block are
+  /// recorded and skipped over so that the rest of the block is still parsed.
+  pub fn parse_block(&mut self) -> ParseResult<Vec<Statement>> {
     let mut block = Vec::new();
-    self.expect(Token::LCurly, "No LCurly token for block");
+    self.expect(Token::LCurly, "No LCurly token for block")?;
+    let block_start = self.span();
     loop {
-      match self.next_opt() {
-        None => panic!("Hit end of file parsing block"),
-        Some(Token::Identifier) => {
-          let ident = self.mk_ident();
-
-          if ident.as_str() == "println" {
-            self.expect(Token::LParen, "No LParen for println statement");
-            let str_lit = self.string_literal();
-            self.expect(Token::RParen, "No RParen for println statement");
-            self.expect(Token::SemiColon, "No semicolon for println statement");
-            block.push(Statement::Wasi(Wasi::Println(str_lit)));
-          } else if ident.as_str() == "print" {
-            self.expect(Token::LParen, "No LParen for print statement");
-            let str_lit = self.string_literal();
-            self.expect(Token::RParen, "No RParen for print statement");
-            self.expect(Token::SemiColon, "No semicolon for print statement");
-            block.push(Statement::Wasi(Wasi::Print(str_lit)));
-          } else if self.peek(Token::Assign) {
-            self.next();
-            self.next();
-            let value = self.mk_number();
-            self.expect(Token::SemiColon, "No semicolon for assignment statement");
-            block.push(Statement::Assignment { name: ident, value });
-          } else {
-            self.expect(Token::LParen, "No LParen for fn statement");
-            self.expect(Token::RParen, "No funcs with more than 0 args for now");
-            self.expect(Token::SemiColon, "No semicolon for fn statement");
-            block.push(Statement::FnCall {
-              name: ident,
-              input: Vec::new(),
-            });
-          }
+      match self.peek_token() {
+        None => {
+          self.errors.push(
+            Diagnostic::error(
+              ErrorCode::UnclosedBlock,
+              "Hit end of file parsing block",
+              self.eof_span(),
+            )
+            .with_label(block_start, "this block is never closed")
+            .with_note("help: add a `}` to close the block"),
+          );
+          break;
+        }
+        Some(Token::State) | Some(Token::Terminating) => {
+          let span = self.lex.peek().unwrap().1.clone();
+          self.errors.push(
+            Diagnostic::error(
+              ErrorCode::UnclosedBlock,
+              "Unclosed block before the next state",
+              span.clone(),
+            )
+            .with_label(block_start, "this block is never closed")
+            .with_label(span, "the next state starts here")
+            .with_note("help: add a `}` to close the block"),
+          );
+          break;
         }
-        Some(Token::Terminate) => {
-          self.expect(Token::SemiColon, "No semicolon for print statement");
-          block.push(Statement::Terminate);
+        Some(Token::RCurly) => {
+          self.next_opt();
+          break;
+        }
+        Some(Token::DocComment) => {
+          self.next_opt();
+          self.errors.push(
+            Diagnostic::warning(
+              ErrorCode::DanglingDocComment,
+              "Doc comment inside a state is ignored",
+              self.span(),
+            )
+            .with_label(self.span(), "this doesn't document anything")
+            .with_note("help: use `//` for a comment inside a state"),
+          );
+        }
+        Some(_) => match self.parse_statement() {
+          Ok(stmt) => block.push(stmt),
+          Err(e) => {
+            self.errors.push(e);
+            self.synchronize_statement();
+          }
+        },
+      }
+    }
+
+    Ok(block)
+  }
+
+  /// Parse a single statement inside of a block
+  pub fn parse_statement(&mut self) -> ParseResult<Statement> {
+    // A token that can't start a statement is left in place so that the
+    // parser can recover from it
+    if let Some((token, span)) = self.lex.peek().cloned() {
+      if !token.starts_statement() {
+        return Err(expected_statement(token, span));
+      }
+    }
+    match self.next()? {
+      Token::Identifier => {
+        let ident = self.mk_ident();
+
+        if ident.as_str() == "println" {
+          Ok(Statement::Wasi(Wasi::Println(self.parse_print_args()?)))
+        } else if ident.as_str() == "print" {
+          Ok(Statement::Wasi(Wasi::Print(self.parse_print_args()?)))
+        } else if self.peek(Token::Assign) || self.peek(Token::Colon) {
+          // Assignments can annotate the type of the variable, `x: i64 <- 5;`
+          let ty = if self.peek(Token::Colon) {
+            self.next()?;
+            Some(self.parse_type()?)
+          } else {
+            None
+          };
+          self.expect(Token::Assign, "No `<-` for assignment statement")?;
+          let value = self.parse_expr()?;
+          self.expect(Token::SemiColon, "No semicolon for assignment statement")?;
+          Ok(Statement::Assignment {
+            name: ident,
+            ty,
+            value,
+          })
+        } else {
+          let input = self.parse_args()?;
+          self.expect(Token::SemiColon, "No semicolon for fn statement")?;
+          Ok(Statement::FnCall { name: ident, input })
         }
-        Some(Token::RCurly) => break,
-        Some(_) => unimplemented!("Assignment etc."),
       }
+      Token::Terminate => {
+        let start = self.span().start;
+        let code = if self.peek(Token::SemiColon) {
+          None
+        } else {
+          Some(self.parse_expr()?)
+        };
+        self.expect(Token::SemiColon, "No semicolon for terminate statement")?;
+        Ok(Statement::Terminate {
+          code,
+          span: start..self.span().end,
+        })
+      }
+      Token::If => self.parse_if(),
+      Token::GoTo => {
+        let name = self.ident()?;
+        // States without parameters can be gone to without the parentheses
+        let input = if self.peek(Token::LParen) {
+          self.parse_args()?
+        } else {
+          Vec::new()
+        };
+        self.expect(Token::SemiColon, "No semicolon for goto statement")?;
+        Ok(Statement::Transition { name, input })
+      }
+      t => Err(expected_statement(t, self.span())),
+    }
+  }
+
+  /// Parse an `if` statement after the `if` token has been consumed. An
+  /// `else if` is parsed as an `if` statement inside of the else block.
+  pub fn parse_if(&mut self) -> ParseResult<Statement> {
+    let condition = self.parse_expr()?;
+    let then_block = self.parse_block()?;
+    let else_block = if self.peek(Token::Else) {
+      self.next()?;
+      if self.peek(Token::If) {
+        self.next()?;
+        vec![self.parse_if()?]
+      } else {
+        self.parse_block()?
+      }
+    } else {
+      Vec::new()
+    };
+    Ok(Statement::If {
+      condition,
+      then_block,
+      else_block,
+    })
+  }
+
+  /// Parse an expression
+  pub fn parse_expr(&mut self) -> ParseResult<Expr> {
+    self.parse_expr_prec(0)
+  }
+
+  /// Parse an expression with precedence climbing. Only binary operators that
+  /// bind at least as tightly as `min_prec` are consumed, all operators are
+  /// left associative.
+  fn parse_expr_prec(&mut self, min_prec: u8) -> ParseResult<Expr> {
+    let lhs = self.parse_primary()?;
+    self.parse_binary(lhs, min_prec)
+  }
+
+  /// Parse the binary operators and their right hand sides that follow an
+  /// already parsed `lhs`
+  fn parse_binary(&mut self, mut lhs: Expr, min_prec: u8) -> ParseResult<Expr> {
+    loop {
+      let op = match self.peek_token().and_then(Token::as_binary_op) {
+        Some(op) if op.precedence() >= min_prec => op,
+        _ => break,
+      };
+      self.next()?;
+      let rhs = self.parse_expr_prec(op.precedence() + 1)?;
+      lhs = Expr::Binary {
+        op,
+        lhs: Box::new(lhs),
+        rhs: Box::new(rhs),
+      };
     }
+    Ok(lhs)
+  }
 
-    block
+  /// Parse a literal, variable, negation, builtin or parenthesized expression
+  fn parse_primary(&mut self) -> ParseResult<Expr> {
+    // A token that can't start an expression is left in place so that the
+    // parser can recover from it
+    if let Some((token, span)) = self.lex.peek().cloned() {
+      if !token.starts_expr() {
+        return Err(expected_expression(token, span));
+      }
+    }
+    match self.next()? {
+      Token::Minus if self.peek(Token::Number) => {
+        let start = self.span().start;
+        self.next()?;
+        Ok(Expr::Literal {
+          value: self.mk_number(true)?,
+          span: start..self.span().end,
+        })
+      }
+      Token::Minus => {
+        let start = self.span().start;
+        let expr = self.parse_primary()?;
+        Ok(Expr::Unary {
+          op: UnaryOp::Neg,
+          span: start..expr.span().end,
+          expr: Box::new(expr),
+        })
+      }
+      Token::Number => Ok(Expr::Literal {
+        value: self.mk_number(false)?,
+        span: self.span(),
+      }),
+      Token::Float => Ok(Expr::Literal {
+        value: self.mk_float()?,
+        span: self.span(),
+      }),
+      Token::True | Token::False => Ok(Expr::Literal {
+        value: SycValue::Bool(self.slice() == "true"),
+        span: self.span(),
+      }),
+      Token::StringLiteral => Ok(Expr::Literal {
+        value: SycValue::Str(self.mk_str_lit()?),
+        span: self.span(),
+      }),
+      Token::Identifier if self.slice() == "len" && self.peek(Token::LParen) => {
+        let start = self.span().start;
+        self.next()?;
+        let expr = self.parse_expr()?;
+        self.expect(Token::RParen, "No RParen to close `len`")?;
+        Ok(Expr::Unary {
+          op: UnaryOp::Len,
+          expr: Box::new(expr),
+          span: start..self.span().end,
+        })
+      }
+      Token::Identifier => Ok(Expr::Variable(self.mk_ident())),
+      Token::LParen => {
+        let expr = self.parse_expr()?;
+        self.expect(Token::RParen, "No RParen to close expression")?;
+        Ok(expr)
+      }
+      t => Err(expected_expression(t, self.span())),
+    }
   }
 
-  /// Parse the input into the final output
-  pub fn parse(mut self) -> Vec<Statement> {
+  /// Parse a state definition after its leading `state` or `terminating`
+  /// token has been consumed
+  pub fn parse_state(&mut self, terminating: bool, docs: Vec<String>) -> ParseResult<Statement> {
+    if terminating {
+      self.expect(Token::State, "No state token after terminating")?;
+    }
+    Ok(Statement::StateDefn {
+      docs,
+      terminating,
+      name: self.ident()?,
+      input: self.parse_params()?,
+      statements: self.parse_block()?,
+    })
+  }
+
+  /// Parse the input into the final output. This returns every state that
+  /// could be parsed along with all of the syntax errors and warnings found on
+  /// the way. The statements should only be used for code generation if there
+  /// are no errors.
+  pub fn parse(mut self) -> (Vec<Statement>, Vec<Diagnostic>) {
     let mut statements = Vec::new();
+    let mut docs = Vec::new();
+    let mut docs_span = 0..0;
     loop {
-      match self.next_opt() {
-        Some(Token::Terminating) => {
-          self.expect(Token::State, "No state token after end");
-          let state_defn = Statement::StateDefn {
-            terminating: true,
-            name: self.ident(),
-            input: self.parse_args(),
-            statements: self.parse_block(),
-          };
-          statements.push(state_defn);
-        }
-        Some(Token::State) => {
-          let state_defn = Statement::StateDefn {
-            terminating: false,
-            name: self.ident(),
-            input: self.parse_args(),
-            statements: self.parse_block(),
-          };
-          statements.push(state_defn);
+      let token = self.next_opt();
+      if token == Some(Token::DocComment) {
+        if docs.is_empty() {
+          docs_span = self.span();
         }
+        docs_span.end = self.span().end;
+        docs.push(self.mk_doc_line());
+        continue;
+      }
+      // Doc comments have to be directly followed by the state they document
+      if !docs.is_empty() && !matches!(token, Some(Token::Terminating | Token::State)) {
+        self.errors.push(dangling_doc_comment(docs_span.clone()));
+        docs.clear();
+      }
+      let result = match token {
+        Some(Token::Terminating) => self.parse_state(true, std::mem::take(&mut docs)),
+        Some(Token::State) => self.parse_state(false, std::mem::take(&mut docs)),
+        Some(Token::Error) => Err(
+          Diagnostic::error(ErrorCode::UnknownToken, "Unknown token", self.span())
+            .with_label(self.span(), "this is not valid sycamore syntax"),
+        ),
+        Some(t) => Err(
+          Diagnostic::error(
+            ErrorCode::UnexpectedToken,
+            format!("Unexpected {}", t),
+            self.span(),
+          )
+          .with_label(self.span(), "expected a state definition"),
+        ),
         None => break,
-        _ => (),
+      };
+      match result {
+        Ok(state_defn) => statements.push(state_defn),
+        Err(e) => {
+          self.errors.push(e);
+          self.synchronize_state();
+        }
       }
     }
 
-    statements
+    (statements, self.errors)
+  }
+}
+
+/// Create the error for a token found where a statement should start
+fn expected_statement(token: Token, span: Range<usize>) -> Diagnostic {
+  match token {
+    Token::Error => Diagnostic::error(ErrorCode::UnknownToken, "Unknown token", span.clone())
+      .with_label(span, "this is not valid sycamore syntax"),
+    t => Diagnostic::error(
+      ErrorCode::UnexpectedToken,
+      format!("Unexpected {}", t),
+      span.clone(),
+    )
+    .with_label(span, "expected a statement"),
+  }
+}
+
+/// Create the error for a token found where an expression should start
+fn expected_expression(token: Token, span: Range<usize>) -> Diagnostic {
+  Diagnostic::error(
+    ErrorCode::UnexpectedToken,
+    "Expected an expression",
+    span.clone(),
+  )
+  .with_label(span, format!("expected an expression, found {}", token))
+}
+
+/// Create the error for a doc comment that isn't followed by a state
+fn dangling_doc_comment(span: Range<usize>) -> Diagnostic {
+  Diagnostic::error(
+    ErrorCode::DanglingDocComment,
+    "Doc comment does not document a state",
+    span.clone(),
+  )
+  .with_label(span, "expected a state after this doc comment")
+  .with_note("help: use `//` for a comment that doesn't document a state")
+}
+
+/// Read a unicode escape such as `\u{1F600}`, where `rest` is everything
+/// after the `\` which is at `start` in the input. Returns the character and
+/// how many bytes long the escape sequence is.
+fn unescape_unicode(rest: &str, start: usize) -> ParseResult<(char, usize)> {
+  let invalid = |len: usize, message: String, label: &str| {
+    let span = start..start + len;
+    Diagnostic::error(ErrorCode::InvalidEscape, message, span.clone()).with_label(span, label)
+  };
+  let close = match rest.find('}') {
+    Some(close) if rest[1..].starts_with('{') => close,
+    _ => {
+      return Err(
+        invalid(
+          2,
+          "Invalid unicode escape".into(),
+          "expected a codepoint in braces",
+        )
+        .with_note("help: write unicode escapes like `\\u{1F600}`"),
+      )
+    }
+  };
+  let len = close + 2;
+  let digits = &rest[2..close];
+  let value = match u32::from_str_radix(digits, 16) {
+    Ok(value) if digits.len() <= 6 && !digits.starts_with('+') => value,
+    _ => {
+      return Err(
+        invalid(
+          len,
+          "Invalid unicode escape".into(),
+          "expected 1 to 6 hex digits",
+        )
+        .with_note("help: write unicode escapes like `\\u{1F600}`"),
+      )
+    }
+  };
+  match char::from_u32(value) {
+    Some(c) => Ok((c, len)),
+    None => Err(invalid(
+      len,
+      format!("`{:X}` is not a valid unicode codepoint", value),
+      "not a unicode character",
+    )),
   }
 }
 
@@ -194,6 +877,18 @@ pub enum Token {
   #[token("equals")]
   Equals,
 
+  // Arithmetic
+  #[token("+")]
+  Plus,
+  #[token("-")]
+  Minus,
+  #[token("*")]
+  Star,
+  #[token("/")]
+  Slash,
+  #[token("%")]
+  Percent,
+
   // Control flow
   #[token("goto")]
   GoTo,
@@ -222,19 +917,315 @@ pub enum Token {
   RCurly,
   #[token(";")]
   SemiColon,
+  #[token(":")]
+  Colon,
+  #[token(",")]
+  Comma,
 
-  #[regex(r#""([^"\\]|\\t|\\u|\\n|\\")*""#)]
+  // Any character can follow a `\` here, invalid escapes are reported when
+  // the literal is parsed
+  #[regex(r#""([^"\\]|\\.)*""#)]
   StringLiteral,
 
   #[regex("[0-9]*")]
   Number,
+  #[regex(r"[0-9]+\.[0-9]+")]
+  Float,
+  #[token("true")]
+  True,
+  #[token("false")]
+  False,
 
-  #[regex("[a-zA-Z$-][a-zA-Z0-9$-]*")]
+  // Words in a name are joined with `_`, a `-` is always a minus so that
+  // `x-1` is a subtraction
+  #[regex("[a-zA-Z$][a-zA-Z0-9$_]*")]
   Identifier,
 
   #[regex(r"[ \t\n\f]+", logos::skip)]
   Whitespace,
 
+  // A `//` comment that isn't a `///` doc comment, which includes comments
+  // starting with four or more slashes
+  #[regex(r"//([^/\n][^\n]*)?|////[^\n]*", logos::skip)]
+  LineComment,
+  #[regex(r"/\*([^*]|\*+[^*/])*\*+/", logos::skip)]
+  BlockComment,
+  /// Documents the state that comes after it
+  #[regex(r"///([^/\n][^\n]*)?")]
+  DocComment,
+
   #[error]
   Error,
 }
+
+impl Token {
+  /// Can a statement start with this token
+  pub fn starts_statement(self) -> bool {
+    matches!(
+      self,
+      Token::Identifier | Token::Terminate | Token::If | Token::GoTo
+    )
+  }
+
+  /// Can an expression start with this token
+  pub fn starts_expr(self) -> bool {
+    matches!(
+      self,
+      Token::Minus
+        | Token::Number
+        | Token::Float
+        | Token::True
+        | Token::False
+        | Token::StringLiteral
+        | Token::Identifier
+        | Token::LParen
+    )
+  }
+
+  /// Get the binary operator this token stands for, if any
+  pub fn as_binary_op(self) -> Option<BinOp> {
+    match self {
+      Token::Equals => Some(BinOp::Equals),
+      Token::And => Some(BinOp::And),
+      Token::Or => Some(BinOp::Or),
+      Token::Plus => Some(BinOp::Add),
+      Token::Minus => Some(BinOp::Sub),
+      Token::Star => Some(BinOp::Mul),
+      Token::Slash => Some(BinOp::Div),
+      Token::Percent => Some(BinOp::Rem),
+      _ => None,
+    }
+  }
+}
+
+impl fmt::Display for Token {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let desc = match self {
+      Token::Assign => "`<-`",
+      Token::And => "`and`",
+      Token::Or => "`or`",
+      Token::Equals => "`equals`",
+      Token::Plus => "`+`",
+      Token::Minus => "`-`",
+      Token::Star => "`*`",
+      Token::Slash => "`/`",
+      Token::Percent => "`%`",
+      Token::GoTo => "`goto`",
+      Token::Terminate => "`terminate`",
+      Token::Unreachable => "`unreachable`",
+      Token::If => "`if`",
+      Token::Else => "`else`",
+      Token::Terminating => "`terminating`",
+      Token::State => "`state`",
+      Token::LParen => "`(`",
+      Token::RParen => "`)`",
+      Token::LCurly => "`{`",
+      Token::RCurly => "`}`",
+      Token::SemiColon => "`;`",
+      Token::Colon => "`:`",
+      Token::Comma => "`,`",
+      Token::StringLiteral => "a string literal",
+      Token::Number => "a number",
+      Token::Float => "a floating point number",
+      Token::True => "`true`",
+      Token::False => "`false`",
+      Token::Identifier => "an identifier",
+      Token::Whitespace => "whitespace",
+      Token::LineComment | Token::BlockComment => "a comment",
+      Token::DocComment => "a doc comment",
+      Token::Error => "an unknown token",
+    };
+    write!(f, "{}", desc)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Split the source into its tokens
+  fn lex(source: &str) -> Vec<Token> {
+    Token::lexer(source).collect()
+  }
+
+  /// Parse the source as a single expression
+  fn expr(source: &str) -> Expr {
+    SycParser::new(source)
+      .parse_expr()
+      .unwrap_or_else(|e| panic!("`{}` did not parse: {}", source, e))
+  }
+
+  #[test]
+  fn minus_is_never_part_of_a_name() {
+    use Token::*;
+    assert_eq!(lex("x-1"), vec![Identifier, Minus, Number]);
+    assert_eq!(lex("x-y"), vec![Identifier, Minus, Identifier]);
+    assert_eq!(lex("my_variable"), vec![Identifier]);
+    assert!(matches!(
+      expr("count-1"),
+      Expr::Binary { op: BinOp::Sub, .. }
+    ));
+  }
+
+  #[test]
+  fn negative_literals_are_typed_with_their_sign() {
+    assert!(matches!(
+      expr("-2147483648"),
+      Expr::Literal {
+        value: SycValue::I32(i32::MIN),
+        span,
+      } if span == (0..11)
+    ));
+    assert!(matches!(
+      expr("-2147483649"),
+      Expr::Literal {
+        value: SycValue::I64(-2147483649),
+        ..
+      }
+    ));
+    assert!(matches!(
+      expr("-x"),
+      Expr::Unary {
+        op: UnaryOp::Neg,
+        ..
+      }
+    ));
+  }
+
+  #[test]
+  fn only_three_slashes_start_a_doc_comment() {
+    use Token::*;
+    assert_eq!(lex("/// docs"), vec![DocComment]);
+    assert_eq!(lex("///"), vec![DocComment]);
+    assert_eq!(lex("// comment"), Vec::new());
+    assert_eq!(lex("//// divider"), Vec::new());
+    assert_eq!(lex("////////"), Vec::new());
+  }
+
+  #[test]
+  fn doc_comment_inside_a_state_is_a_warning() {
+    let source = r#"
+      //// Not the docs
+      /// The docs
+      terminating state main() {
+        /// Not the docs either
+        println("hi");
+      }
+    "#;
+    let (program, diagnostics) = SycParser::new(source).parse();
+    assert_eq!(diagnostics.len(), 1);
+    assert!(!diagnostics[0].is_error());
+    assert_eq!(diagnostics[0].code, ErrorCode::DanglingDocComment);
+    match &program[..] {
+      [Statement::StateDefn {
+        docs, statements, ..
+      }] => {
+        assert_eq!(docs, &["The docs"]);
+        assert_eq!(statements.len(), 1);
+      }
+      program => panic!("expected a single state, found {:?}", program),
+    }
+  }
+
+  #[test]
+  fn errors_in_one_place_do_not_stop_parsing() {
+    let source = r#"
+      terminating state main() {
+        x <- ;
+        println("after");
+      }
+      state broken( {
+      }
+      state fine() {
+        println("ok");
+      }
+    "#;
+    let (program, diagnostics) = SycParser::new(source).parse();
+    let codes: Vec<_> = diagnostics.iter().map(|d| d.code).collect();
+    assert_eq!(
+      codes,
+      [ErrorCode::UnexpectedToken, ErrorCode::UnexpectedToken]
+    );
+    let names: Vec<_> = program
+      .iter()
+      .filter_map(|stmt| match stmt {
+        Statement::StateDefn { name, .. } => Some(name.as_str()),
+        _ => None,
+      })
+      .collect();
+    assert_eq!(names, ["main", "fine"]);
+    // The statement after the broken one is still there
+    match &program[0] {
+      Statement::StateDefn { statements, .. } => {
+        assert!(matches!(
+          statements[..],
+          [Statement::Wasi(Wasi::Println(_))]
+        ));
+      }
+      stmt => panic!("expected a state, found {:?}", stmt),
+    }
+  }
+
+  #[test]
+  fn broken_if_does_not_throw_off_blocks() {
+    let source = r#"
+      terminating state main() {
+        if {
+          println("skipped");
+        }
+        println("after");
+        ;
+        println("last");
+      }
+    "#;
+    let (program, diagnostics) = SycParser::new(source).parse();
+    let codes: Vec<_> = diagnostics.iter().map(|d| d.code).collect();
+    assert_eq!(
+      codes,
+      [ErrorCode::UnexpectedToken, ErrorCode::UnexpectedToken]
+    );
+    match &program[..] {
+      [Statement::StateDefn { statements, .. }] => assert!(matches!(
+        statements[..],
+        [
+          Statement::Wasi(Wasi::Println(_)),
+          Statement::Wasi(Wasi::Println(_))
+        ]
+      )),
+      program => panic!("expected a single state, found {:?}", program),
+    }
+  }
+
+  #[test]
+  fn unclosed_block_stops_at_the_next_state() {
+    let source = r#"
+      terminating state main() {
+        println("never closed");
+      state next() {
+        println("ok");
+      }
+    "#;
+    let (program, diagnostics) = SycParser::new(source).parse();
+    let codes: Vec<_> = diagnostics.iter().map(|d| d.code).collect();
+    assert_eq!(codes, [ErrorCode::UnclosedBlock]);
+    assert_eq!(program.len(), 2);
+  }
+
+  #[test]
+  fn unknown_escape_points_at_the_escape() {
+    let source = r#"terminating state main() { println("bad \q"); }"#;
+    let (_, diagnostics) = SycParser::new(source).parse();
+    let start = source.find(r"\q").unwrap();
+    assert_eq!(diagnostics[0].code, ErrorCode::InvalidEscape);
+    assert_eq!(diagnostics[0].span, start..start + 2);
+  }
+
+  #[test]
+  fn codepoint_past_the_last_one_is_an_invalid_escape() {
+    let source = r#"terminating state main() { println("\u{110000}"); }"#;
+    let (_, diagnostics) = SycParser::new(source).parse();
+    let start = source.find(r"\u").unwrap();
+    assert_eq!(diagnostics[0].code, ErrorCode::InvalidEscape);
+    assert_eq!(diagnostics[0].span, start..start + r"\u{110000}".len());
+  }
+}
@@ -0,0 +1,392 @@
+use crate::{
+  diagnostics::Diagnostic,
+  error::ErrorCode,
+  types::{Expr, Ident, Statement},
+  wasi::{PrintArg, Wasi},
+};
+use std::collections::{HashMap, HashSet};
+
+/// Checks that every name in a program refers to something that exists. This
+/// covers the states that are called or transitioned to, as well as the
+/// variables read in each state, which must have a value assigned to them on
+/// every path to where they are read.
+pub struct Resolver {
+  /// Every state in the program by name, pointing at its first definition
+  states: HashMap<String, Ident>,
+  /// Every parameter and variable in the state being resolved, pointing at
+  /// where it first appears
+  vars: HashMap<String, Ident>,
+  errors: Vec<Diagnostic>,
+}
+
+impl Resolver {
+  /// Create a `Resolver` for a program, reporting any states that are
+  /// defined more than once
+  pub fn new(program: &[Statement]) -> Self {
+    let mut states: HashMap<String, Ident> = HashMap::new();
+    let mut errors = Vec::new();
+    for stmt in program {
+      if let Statement::StateDefn { name, .. } = stmt {
+        match states.get(name.as_str()) {
+          Some(first) => errors.push(
+            Diagnostic::error(
+              ErrorCode::DuplicateState,
+              format!("the state `{}` is defined more than once", name.as_str()),
+              name.span(),
+            )
+            .with_label(name.span(), "defined again here")
+            .with_label(first.span(), "first defined here"),
+          ),
+          None => {
+            states.insert(name.as_str().to_string(), name.clone());
+          }
+        }
+      }
+    }
+    Self {
+      states,
+      vars: HashMap::new(),
+      errors,
+    }
+  }
+
+  /// Resolve every name in the program
+  pub fn resolve(mut self, program: &[Statement]) -> Vec<Diagnostic> {
+    self.check_main(program);
+    for stmt in program {
+      if let Statement::StateDefn {
+        input, statements, ..
+      } = stmt
+      {
+        self.vars.clear();
+        let mut assigned = HashSet::new();
+        for param in input {
+          if let Some(first) = self.vars.get(param.name.as_str()) {
+            self.errors.push(duplicate_variable(&param.name, first));
+            continue;
+          }
+          self
+            .vars
+            .insert(param.name.as_str().to_string(), param.name.clone());
+          assigned.insert(param.name.as_str().to_string());
+        }
+        collect_assignments(statements, &mut self.vars);
+        self.resolve_block(statements, &mut assigned);
+      }
+    }
+    self.errors
+  }
+
+  /// Check that the program has a `main` state to start in, and that it
+  /// takes no arguments and is allowed to end the program
+  fn check_main(&mut self, program: &[Statement]) {
+    let main = program.iter().find_map(|stmt| match stmt {
+      Statement::StateDefn {
+        name,
+        terminating,
+        input,
+        ..
+      } if name.as_str() == "main" => Some((name, *terminating, input)),
+      _ => None,
+    });
+    let (name, terminating, input) = match main {
+      Some(main) => main,
+      None => {
+        self.errors.push(
+          Diagnostic::error(ErrorCode::MissingMain, "no `main` state defined", 0..0)
+            .with_note("help: add a `terminating state main()` to start the program in"),
+        );
+        return;
+      }
+    };
+    if !terminating {
+      self.errors.push(
+        Diagnostic::error(
+          ErrorCode::MainNotTerminating,
+          "`main` must be labelled an end state",
+          name.span(),
+        )
+        .with_label(name.span(), "`main` is not a terminating state")
+        .with_note("help: declare it as `terminating state main()`"),
+      );
+    }
+    if !input.is_empty() {
+      self.errors.push(
+        Diagnostic::error(
+          ErrorCode::MainHasArgs,
+          "`main` must have no arguments",
+          name.span(),
+        )
+        .with_label(name.span(), "`main` is declared with arguments"),
+      );
+    }
+  }
+
+  /// Resolve the names in a block. `assigned` holds the variables that have
+  /// a value on every path to the current statement.
+  fn resolve_block(&mut self, statements: &[Statement], assigned: &mut HashSet<String>) {
+    for stmt in statements {
+      match stmt {
+        Statement::Assignment { name, ty, value } => {
+          self.resolve_expr(value, assigned);
+          // Variables live for the whole state so annotating one that already
+          // exists would declare it a second time
+          if ty.is_some() && assigned.contains(name.as_str()) {
+            self.errors.push(
+              duplicate_variable(name, &self.vars[name.as_str()])
+                .with_note("help: remove the type annotation to assign a new value to it"),
+            );
+          }
+          assigned.insert(name.as_str().to_string());
+        }
+        Statement::FnCall { name, input } | Statement::Transition { name, input } => {
+          if !self.states.contains_key(name.as_str()) {
+            let mut diagnostic = Diagnostic::error(
+              ErrorCode::UnknownState,
+              format!("no state named `{}`", name.as_str()),
+              name.span(),
+            )
+            .with_label(name.span(), "this state is not defined");
+            if let Some(similar) = suggest(name.as_str(), self.states.keys()) {
+              diagnostic = diagnostic.with_note(format!("help: did you mean `{}`?", similar));
+            }
+            self.errors.push(diagnostic);
+          }
+          for arg in input {
+            self.resolve_expr(arg, assigned);
+          }
+        }
+        Statement::If {
+          condition,
+          then_block,
+          else_block,
+        } => {
+          self.resolve_expr(condition, assigned);
+          let mut then_assigned = assigned.clone();
+          self.resolve_block(then_block, &mut then_assigned);
+          let mut else_assigned = assigned.clone();
+          self.resolve_block(else_block, &mut else_assigned);
+          // A branch that leaves the state never gets to the code after the
+          // `if`, so only the other branch decides what has a value there
+          *assigned = match (diverges(then_block), diverges(else_block)) {
+            (true, false) => else_assigned,
+            (false, true) => then_assigned,
+            _ => then_assigned
+              .intersection(&else_assigned)
+              .cloned()
+              .collect(),
+          };
+        }
+        Statement::Wasi(Wasi::Print(args) | Wasi::Println(args)) => {
+          for arg in args {
+            if let PrintArg::Expr(expr) = arg {
+              self.resolve_expr(expr, assigned);
+            }
+          }
+        }
+        Statement::Terminate {
+          code: Some(code), ..
+        } => self.resolve_expr(code, assigned),
+        Statement::Terminate { code: None, .. } | Statement::StateDefn { .. } => (),
+      }
+    }
+  }
+
+  /// Resolve the variables read in an expression
+  fn resolve_expr(&mut self, expr: &Expr, assigned: &HashSet<String>) {
+    match expr {
+      Expr::Literal { .. } => (),
+      Expr::Variable(name) if assigned.contains(name.as_str()) => (),
+      Expr::Variable(name) => match self.vars.get(name.as_str()) {
+        Some(first) => self.errors.push(
+          Diagnostic::error(
+            ErrorCode::UseBeforeAssign,
+            format!("`{}` is used before it is assigned", name.as_str()),
+            name.span(),
+          )
+          .with_label(name.span(), "used here without a value")
+          .with_label(first.span(), "assigned here")
+          .with_note("help: assign it a value before this on every path through the state"),
+        ),
+        None => {
+          let mut diagnostic = Diagnostic::error(
+            ErrorCode::UnknownVariable,
+            format!("no variable named `{}`", name.as_str()),
+            name.span(),
+          )
+          .with_label(name.span(), "this variable is never assigned in this state");
+          if let Some(similar) = suggest(name.as_str(), self.vars.keys()) {
+            diagnostic = diagnostic.with_note(format!("help: did you mean `{}`?", similar));
+          }
+          self.errors.push(diagnostic);
+        }
+      },
+      Expr::Unary { expr, .. } => self.resolve_expr(expr, assigned),
+      Expr::Binary { lhs, rhs, .. } => {
+        self.resolve_expr(lhs, assigned);
+        self.resolve_expr(rhs, assigned);
+      }
+    }
+  }
+}
+
+/// Record where every variable in the given statements is first assigned
+/// unless it is already known
+fn collect_assignments(statements: &[Statement], vars: &mut HashMap<String, Ident>) {
+  for stmt in statements {
+    match stmt {
+      Statement::Assignment { name, .. } => {
+        vars
+          .entry(name.as_str().to_string())
+          .or_insert_with(|| name.clone());
+      }
+      Statement::If {
+        then_block,
+        else_block,
+        ..
+      } => {
+        collect_assignments(then_block, vars);
+        collect_assignments(else_block, vars);
+      }
+      _ => (),
+    }
+  }
+}
+
+/// Does the block always leave the state before reaching its end
+fn diverges(statements: &[Statement]) -> bool {
+  statements.iter().any(|stmt| match stmt {
+    Statement::Transition { .. } | Statement::Terminate { .. } => true,
+    Statement::If {
+      then_block,
+      else_block,
+      ..
+    } => diverges(then_block) && diverges(else_block),
+    _ => false,
+  })
+}
+
+/// Create the error for a parameter or variable that is declared twice
+fn duplicate_variable(name: &Ident, first: &Ident) -> Diagnostic {
+  Diagnostic::error(
+    ErrorCode::DuplicateVariable,
+    format!("`{}` is already declared in this state", name.as_str()),
+    name.span(),
+  )
+  .with_label(name.span(), "declared again here")
+  .with_label(first.span(), "first declared here")
+}
+
+/// Find the name closest to `name` if any are close enough to be a typo
+fn suggest<'a>(name: &str, candidates: impl Iterator<Item = &'a String>) -> Option<&'a str> {
+  // Allow about one mistake for every three characters
+  let max_distance = std::cmp::max(name.chars().count() / 3, 1);
+  candidates
+    .map(|candidate| (edit_distance(name, candidate), candidate))
+    .filter(|(distance, _)| *distance <= max_distance)
+    .min_by(|(a, a_name), (b, b_name)| a.cmp(b).then_with(|| a_name.cmp(b_name)))
+    .map(|(_, candidate)| candidate.as_str())
+}
+
+/// The Levenshtein distance between two strings, the fewest single character
+/// insertions, deletions or substitutions that turn one into the other
+fn edit_distance(a: &str, b: &str) -> usize {
+  let b: Vec<char> = b.chars().collect();
+  let mut prev: Vec<usize> = (0..=b.len()).collect();
+  let mut current = vec![0; b.len() + 1];
+  for (i, a_char) in a.chars().enumerate() {
+    current[0] = i + 1;
+    for (j, b_char) in b.iter().enumerate() {
+      let substitution = prev[j] + if a_char == *b_char { 0 } else { 1 };
+      current[j + 1] = substitution.min(prev[j + 1] + 1).min(current[j] + 1);
+    }
+    std::mem::swap(&mut prev, &mut current);
+  }
+  prev[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::parser::SycParser;
+  use std::ops::Range;
+
+  /// Resolve a program that parses cleanly, returning the problems found
+  fn resolve(source: &str) -> Vec<Diagnostic> {
+    let (program, errors) = SycParser::new(source).parse();
+    assert!(errors.is_empty(), "{:?}", errors);
+    Resolver::new(&program).resolve(&program)
+  }
+
+  /// The span of the `nth` place `needle` appears in `source`
+  fn span_of(source: &str, needle: &str, nth: usize) -> Range<usize> {
+    let start = source.match_indices(needle).nth(nth).unwrap().0;
+    start..start + needle.len()
+  }
+
+  #[test]
+  fn unknown_state_is_reported_where_it_is_named() {
+    let source = "terminating state main() { goto nowhere; }";
+    let errors = resolve(source);
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].code, ErrorCode::UnknownState);
+    assert_eq!(errors[0].span, span_of(source, "nowhere", 0));
+  }
+
+  #[test]
+  fn similar_state_is_suggested() {
+    let source = r#"
+      terminating state main() { goto fnish; }
+      terminating state finish() { terminate; }
+    "#;
+    let errors = resolve(source);
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].code, ErrorCode::UnknownState);
+    assert_eq!(errors[0].notes, ["help: did you mean `finish`?"]);
+  }
+
+  #[test]
+  fn duplicate_state_points_at_both_definitions() {
+    let source = r#"
+      terminating state main() { goto done; }
+      terminating state done() { terminate; }
+      terminating state done() { terminate 1; }
+    "#;
+    let errors = resolve(source);
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].code, ErrorCode::DuplicateState);
+    assert_eq!(errors[0].span, span_of(source, "done", 2));
+    assert_eq!(errors[0].labels[1].span, span_of(source, "done", 1));
+  }
+
+  #[test]
+  fn variable_assigned_on_one_branch_is_used_before_assign() {
+    let source = r#"
+      terminating state main() {
+        if true {
+          x <- 1;
+        }
+        terminate x;
+      }
+    "#;
+    let errors = resolve(source);
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].code, ErrorCode::UseBeforeAssign);
+    assert_eq!(errors[0].span, span_of(source, "x", 1));
+  }
+
+  #[test]
+  fn annotating_a_variable_twice_is_a_duplicate() {
+    let source = r#"
+      terminating state main() {
+        count: i32 <- 1;
+        count: i32 <- 2;
+      }
+    "#;
+    let errors = resolve(source);
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].code, ErrorCode::DuplicateVariable);
+    assert_eq!(errors[0].span, span_of(source, "count", 1));
+    assert_eq!(errors[0].labels[1].span, span_of(source, "count", 0));
+  }
+}
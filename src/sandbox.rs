@@ -0,0 +1,325 @@
+use crate::memory::PAGE_SIZE;
+use serde::{Deserialize, Serialize};
+use std::{fmt, path::PathBuf, str::FromStr};
+use wasi_common::{DirCaps, FileCaps};
+use wasmtime::{StoreLimits, StoreLimitsBuilder};
+
+/// Something a program can be allowed to do through WASI
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Capability {
+  /// Read from standard input
+  Stdin,
+  /// Write to standard output
+  Stdout,
+  /// Write to standard error
+  Stderr,
+  /// See the arguments it was run with
+  Args,
+  /// See environment variables
+  Env,
+}
+
+impl Capability {
+  /// Every capability a program can have
+  pub const ALL: [Capability; 5] = [
+    Capability::Stdin,
+    Capability::Stdout,
+    Capability::Stderr,
+    Capability::Args,
+    Capability::Env,
+  ];
+}
+
+impl FromStr for Capability {
+  type Err = String;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "stdin" => Ok(Capability::Stdin),
+      "stdout" => Ok(Capability::Stdout),
+      "stderr" => Ok(Capability::Stderr),
+      "args" => Ok(Capability::Args),
+      "env" => Ok(Capability::Env),
+      _ => Err(format!(
+        "unknown capability `{}`, expected one of stdin, stdout, stderr, args or env",
+        s
+      )),
+    }
+  }
+}
+
+impl fmt::Display for Capability {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let capability = match self {
+      Capability::Stdin => "stdin",
+      Capability::Stdout => "stdout",
+      Capability::Stderr => "stderr",
+      Capability::Args => "args",
+      Capability::Env => "env",
+    };
+    write!(f, "{}", capability)
+  }
+}
+
+/// What a program can do with the files in a preopened directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DirAccess {
+  /// List, stat and read files
+  ReadOnly,
+  /// Anything, including creating, writing and removing files
+  ReadWrite,
+}
+
+impl DirAccess {
+  /// The WASI rights for the directory and the files opened in it
+  pub fn caps(&self) -> (DirCaps, FileCaps) {
+    match self {
+      DirAccess::ReadOnly => (
+        DirCaps::OPEN
+          | DirCaps::READDIR
+          | DirCaps::READLINK
+          | DirCaps::PATH_FILESTAT_GET
+          | DirCaps::FILESTAT_GET,
+        FileCaps::READ | FileCaps::SEEK | FileCaps::TELL | FileCaps::FILESTAT_GET,
+      ),
+      DirAccess::ReadWrite => (DirCaps::all(), FileCaps::all()),
+    }
+  }
+}
+
+/// A directory on the host that a program can access
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PreopenedDir {
+  /// Where the directory is on the host
+  pub host: PathBuf,
+  /// The path the program sees the directory at
+  pub guest: String,
+  pub access: DirAccess,
+}
+
+impl PreopenedDir {
+  /// Parse a directory given as `HOST` or `HOST:GUEST`. Without a guest path
+  /// the program sees the directory at the same path as the host. The guest
+  /// path is after the last `:`, so that host paths with a drive such as
+  /// `C:\data` work on Windows.
+  pub fn parse(s: &str, access: DirAccess) -> Self {
+    let (host, guest) = match s.rsplit_once(':') {
+      Some((drive, _)) if is_drive(drive) => (s, s),
+      Some(split) => split,
+      None => (s, s),
+    };
+    Self {
+      host: host.into(),
+      guest: guest.into(),
+      access,
+    }
+  }
+}
+
+/// Is this the drive at the start of a Windows path, like the `C` in `C:\`
+fn is_drive(s: &str) -> bool {
+  cfg!(windows) && s.len() == 1 && s.chars().all(|c| c.is_ascii_alphabetic())
+}
+
+/// What a program is allowed to do when it runs. The policy a program is
+/// compiled with is kept in its `SycContext`, and a host can run it with a
+/// different one instead. Directories and environment variables are only
+/// ever granted by the host, a compiled policy can't give them to itself.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SandboxPolicy {
+  /// The most pages of memory the program can grow to
+  pub max_memory_pages: Option<u32>,
+  /// The WASI capabilities the program has
+  pub capabilities: Vec<Capability>,
+  /// The directories the program can access
+  pub dirs: Vec<PreopenedDir>,
+  /// The only environment variables the program can see, if it is limited
+  pub env_allowlist: Option<Vec<String>>,
+}
+
+impl Default for SandboxPolicy {
+  /// A policy that allows every capability except seeing the environment,
+  /// with no memory limit and no directories
+  fn default() -> Self {
+    Self {
+      max_memory_pages: None,
+      capabilities: vec![
+        Capability::Stdin,
+        Capability::Stdout,
+        Capability::Stderr,
+        Capability::Args,
+      ],
+      dirs: Vec::new(),
+      env_allowlist: None,
+    }
+  }
+}
+
+impl SandboxPolicy {
+  /// Does the policy give the program the capability
+  pub fn allows(&self, capability: Capability) -> bool {
+    self.capabilities.contains(&capability)
+  }
+
+  /// Can the program see the environment variable with the given name
+  pub fn allows_env(&self, name: &str) -> bool {
+    self.allows(Capability::Env)
+      && self
+        .env_allowlist
+        .as_ref()
+        .map_or(true, |allowed| allowed.iter().any(|var| var == name))
+  }
+
+  /// Replace the parts of the policy that are given in `overrides`, keeping
+  /// the rest as they are. Naming the environment variables the program can
+  /// see lets it see them, unless the capabilities are replaced as well.
+  pub fn merge(mut self, overrides: &SandboxOverrides) -> Self {
+    if let Some(pages) = overrides.max_memory_pages {
+      self.max_memory_pages = Some(pages);
+    }
+    if let Some(capabilities) = &overrides.capabilities {
+      self.capabilities = capabilities.clone();
+    }
+    if let Some(dirs) = &overrides.dirs {
+      self.dirs = dirs.clone();
+    }
+    if let Some(allowlist) = &overrides.env_allowlist {
+      self.env_allowlist = Some(allowlist.clone());
+      if overrides.capabilities.is_none() && !self.allows(Capability::Env) {
+        self.capabilities.push(Capability::Env);
+      }
+    }
+    self
+  }
+
+  /// Take away the directories and environment variables from a policy that
+  /// was read out of a compiled module. The module can't be trusted to grant
+  /// access to the host, so only what it limits is kept.
+  pub fn without_grants(mut self) -> Self {
+    self.dirs = Vec::new();
+    self.env_allowlist = Some(Vec::new());
+    self
+  }
+
+  /// The limits to put on the store the program runs in
+  pub fn limits(&self) -> StoreLimits {
+    let mut limits = StoreLimitsBuilder::new();
+    if let Some(pages) = self.max_memory_pages {
+      limits = limits.memory_size(pages as usize * PAGE_SIZE as usize);
+    }
+    limits.build()
+  }
+}
+
+/// Parts of a `SandboxPolicy` to replace when a program runs, such as the
+/// ones given on the command line. Anything left as `None` is kept from the
+/// policy the program was compiled with.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SandboxOverrides {
+  pub max_memory_pages: Option<u32>,
+  pub capabilities: Option<Vec<Capability>>,
+  pub dirs: Option<Vec<PreopenedDir>>,
+  pub env_allowlist: Option<Vec<String>>,
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn overrides_only_replace_what_they_give() {
+    let compiled = SandboxPolicy {
+      max_memory_pages: Some(4),
+      capabilities: vec![Capability::Stdout],
+      dirs: vec![PreopenedDir::parse("data", DirAccess::ReadOnly)],
+      env_allowlist: Some(vec!["HOME".into()]),
+    };
+    assert_eq!(
+      compiled.clone().merge(&SandboxOverrides::default()),
+      compiled
+    );
+
+    let merged = compiled.clone().merge(&SandboxOverrides {
+      max_memory_pages: Some(8),
+      ..SandboxOverrides::default()
+    });
+    assert_eq!(merged.max_memory_pages, Some(8));
+    assert_eq!(merged.capabilities, compiled.capabilities);
+    assert_eq!(merged.dirs, compiled.dirs);
+    assert_eq!(merged.env_allowlist, compiled.env_allowlist);
+
+    let merged = compiled.merge(&SandboxOverrides {
+      capabilities: Some(Capability::ALL.to_vec()),
+      env_allowlist: Some(Vec::new()),
+      ..SandboxOverrides::default()
+    });
+    assert_eq!(merged.max_memory_pages, Some(4));
+    assert!(merged.allows(Capability::Stdin));
+    assert!(!merged.allows_env("HOME"));
+  }
+
+  #[test]
+  fn default_policy_hides_the_environment() {
+    let policy = SandboxPolicy::default();
+    assert!(policy.allows(Capability::Stdout));
+    assert!(!policy.allows_env("HOME"));
+
+    let merged = policy.merge(&SandboxOverrides {
+      env_allowlist: Some(vec!["HOME".into()]),
+      ..SandboxOverrides::default()
+    });
+    assert!(merged.allows_env("HOME"));
+    assert!(!merged.allows_env("PATH"));
+  }
+
+  #[test]
+  fn compiled_policy_cannot_grant_dirs_or_env() {
+    let compiled = SandboxPolicy {
+      max_memory_pages: Some(4),
+      capabilities: Capability::ALL.to_vec(),
+      dirs: vec![PreopenedDir::parse("/", DirAccess::ReadWrite)],
+      env_allowlist: None,
+    };
+    let policy = compiled.without_grants();
+    assert_eq!(policy.max_memory_pages, Some(4));
+    assert_eq!(policy.dirs, Vec::new());
+    assert!(!policy.allows_env("HOME"));
+
+    let dirs = vec![PreopenedDir::parse("data", DirAccess::ReadOnly)];
+    let merged = policy.merge(&SandboxOverrides {
+      dirs: Some(dirs.clone()),
+      env_allowlist: Some(vec!["HOME".into()]),
+      ..SandboxOverrides::default()
+    });
+    assert_eq!(merged.dirs, dirs);
+    assert!(merged.allows_env("HOME"));
+  }
+
+  #[test]
+  fn guest_path_comes_after_the_last_colon() {
+    let dir = PreopenedDir::parse("data", DirAccess::ReadOnly);
+    assert_eq!(
+      (dir.host, dir.guest),
+      (PathBuf::from("data"), "data".into())
+    );
+    let dir = PreopenedDir::parse("/srv/data:/data", DirAccess::ReadOnly);
+    assert_eq!(
+      (dir.host, dir.guest),
+      (PathBuf::from("/srv/data"), "/data".into())
+    );
+    let dir = PreopenedDir::parse(r"C:\data:/data", DirAccess::ReadWrite);
+    assert_eq!(
+      (dir.host, dir.guest),
+      (PathBuf::from(r"C:\data"), "/data".into())
+    );
+  }
+
+  #[cfg(windows)]
+  #[test]
+  fn drive_is_not_a_guest_path() {
+    let dir = PreopenedDir::parse(r"C:\data", DirAccess::ReadOnly);
+    assert_eq!(
+      (dir.host, dir.guest),
+      (PathBuf::from(r"C:\data"), r"C:\data".into())
+    );
+  }
+}
@@ -0,0 +1,437 @@
+use crate::{
+  diagnostics::Diagnostic,
+  error::ErrorCode,
+  types::{BinOp, Expr, Ident, Param, Statement, Type, UnaryOp},
+  wasi::{PrintArg, Wasi},
+};
+use std::{collections::HashMap, ops::Range};
+
+/// Checks that every value in a program is used where its type is expected.
+/// Variables get the type they are annotated with, or else the type of the
+/// first value assigned to them. The types found for variables and literals
+/// are written back into the program for code generation.
+pub struct TypeChecker {
+  /// The name and parameters of every state
+  states: HashMap<String, (Ident, Vec<Param>)>,
+  /// The type of every variable in the state being checked along with where
+  /// it got that type
+  vars: HashMap<String, (Type, Ident)>,
+  errors: Vec<Diagnostic>,
+}
+
+impl TypeChecker {
+  /// Create a `TypeChecker` for a program
+  pub fn new(program: &[Statement]) -> Self {
+    let mut states = HashMap::new();
+    for stmt in program {
+      if let Statement::StateDefn { name, input, .. } = stmt {
+        states.insert(name.as_str().to_string(), (name.clone(), input.clone()));
+      }
+    }
+    Self {
+      states,
+      vars: HashMap::new(),
+      errors: Vec::new(),
+    }
+  }
+
+  /// Check the types of the whole program, filling in the types of variables
+  /// that were not annotated
+  pub fn check(mut self, program: &mut [Statement]) -> Vec<Diagnostic> {
+    for stmt in program.iter_mut() {
+      if let Statement::StateDefn {
+        input, statements, ..
+      } = stmt
+      {
+        self.vars = input
+          .iter()
+          .map(|param| {
+            (
+              param.name.as_str().to_string(),
+              (param.ty, param.name.clone()),
+            )
+          })
+          .collect();
+        self.check_block(statements);
+      }
+    }
+    self.errors
+  }
+
+  /// Check every statement in a block, carrying on past any errors
+  fn check_block(&mut self, statements: &mut [Statement]) {
+    for stmt in statements {
+      if let Err(e) = self.check_statement(stmt) {
+        self.errors.push(e);
+      }
+    }
+  }
+
+  fn check_statement(&mut self, stmt: &mut Statement) -> Result<(), Diagnostic> {
+    match stmt {
+      Statement::Assignment { name, ty, value } => {
+        let (expected, reason) = match self.vars.get(name.as_str()) {
+          Some((var_ty, declared)) => {
+            if let Some(annotated) = *ty {
+              if annotated != *var_ty {
+                return Err(
+                  Diagnostic::error(
+                    ErrorCode::TypeMismatch,
+                    format!(
+                      "variable `{}` is annotated as `{}` but it is a `{}`",
+                      name.as_str(),
+                      annotated,
+                      var_ty
+                    ),
+                    name.span(),
+                  )
+                  .with_label(name.span(), format!("annotated as `{}`", annotated))
+                  .with_label(
+                    declared.span(),
+                    format!("`{}` is a `{}` from here", name.as_str(), var_ty),
+                  ),
+                );
+              }
+            }
+            (Some(*var_ty), declared.span())
+          }
+          None => (*ty, name.span()),
+        };
+        let found = self.check_expr(value, expected)?;
+        if let Some(expected) = expected {
+          if found != expected {
+            return Err(
+              mismatch(value.span(), expected, found)
+                .with_label(reason, format!("`{}` is a `{}`", name.as_str(), expected)),
+            );
+          }
+        }
+        *ty = Some(found);
+        self
+          .vars
+          .entry(name.as_str().to_string())
+          .or_insert_with(|| (found, name.clone()));
+      }
+      Statement::FnCall { name, input } | Statement::Transition { name, input } => {
+        // Calls to states that don't exist are reported during codegen
+        let (state, params) = match self.states.get(name.as_str()) {
+          Some(state) => state.clone(),
+          None => return Ok(()),
+        };
+        if input.len() != params.len() {
+          let plural = |n: usize| if n == 1 { "argument" } else { "arguments" };
+          return Err(
+            Diagnostic::error(
+              ErrorCode::ArityMismatch,
+              format!(
+                "state `{}` takes {} {} but {} {} given",
+                name.as_str(),
+                params.len(),
+                plural(params.len()),
+                input.len(),
+                if input.len() == 1 { "was" } else { "were" },
+              ),
+              name.span(),
+            )
+            .with_label(
+              name.span(),
+              format!("expected {} {}", params.len(), plural(params.len())),
+            )
+            .with_label(state.span(), "state defined here"),
+          );
+        }
+        for (arg, param) in input.iter_mut().zip(&params) {
+          let found = self.check_expr(arg, Some(param.ty))?;
+          if found != param.ty {
+            return Err(
+              mismatch(arg.span(), param.ty, found)
+                .with_label(param.name.span(), "parameter defined here"),
+            );
+          }
+        }
+      }
+      Statement::If {
+        condition,
+        then_block,
+        else_block,
+      } => {
+        let found = self.check_expr(condition, Some(Type::Bool))?;
+        if found != Type::Bool {
+          self.errors.push(
+            mismatch(condition.span(), Type::Bool, found)
+              .with_note("help: compare values with `equals` to get a `bool`"),
+          );
+        }
+        self.check_block(then_block);
+        self.check_block(else_block);
+      }
+      Statement::Wasi(Wasi::Print(args) | Wasi::Println(args)) => {
+        for arg in args {
+          if let PrintArg::Expr(expr) = arg {
+            let found = self.check_expr(expr, None)?;
+            if found.is_float() {
+              return Err(
+                Diagnostic::error(
+                  ErrorCode::TypeMismatch,
+                  format!("values of type `{}` can't be printed yet", found),
+                  expr.span(),
+                )
+                .with_label(expr.span(), format!("this is a `{}`", found)),
+              );
+            }
+          }
+        }
+      }
+      Statement::Terminate {
+        code: Some(code), ..
+      } => {
+        let found = self.check_expr(code, Some(Type::I32))?;
+        if found != Type::I32 {
+          return Err(
+            mismatch(code.span(), Type::I32, found).with_note("help: exit codes are `i32` values"),
+          );
+        }
+      }
+      Statement::Terminate { code: None, .. } | Statement::StateDefn { .. } => (),
+    }
+    Ok(())
+  }
+
+  /// Find the type of an expression. Number literals take on the `expected`
+  /// type when they can hold it, so `x: i64 <- 5;` makes `5` an `i64`.
+  fn check_expr(&mut self, expr: &mut Expr, expected: Option<Type>) -> Result<Type, Diagnostic> {
+    match expr {
+      Expr::Literal { value, .. } => {
+        if let Some(coerced) = expected.and_then(|ty| value.coerce(ty)) {
+          *value = coerced;
+        }
+        Ok(value.ty())
+      }
+      Expr::Variable(name) => match self.vars.get(name.as_str()) {
+        Some((ty, _)) => Ok(*ty),
+        None => Err(
+          Diagnostic::error(
+            ErrorCode::UnknownVariable,
+            format!("no variable named `{}`", name.as_str()),
+            name.span(),
+          )
+          .with_label(name.span(), "this variable is never assigned in this state"),
+        ),
+      },
+      Expr::Unary {
+        op: UnaryOp::Len,
+        expr,
+        ..
+      } => {
+        let found = self.check_expr(expr, Some(Type::String))?;
+        if found != Type::String {
+          return Err(
+            mismatch(expr.span(), Type::String, found)
+              .with_note("help: `len` gets the length of a `string` in bytes"),
+          );
+        }
+        Ok(Type::I32)
+      }
+      Expr::Unary {
+        op: UnaryOp::Neg,
+        expr,
+        span,
+      } => {
+        let ty = self.check_expr(expr, expected)?;
+        if !ty.is_numeric() {
+          return Err(
+            Diagnostic::error(
+              ErrorCode::TypeMismatch,
+              format!("cannot negate a value of type `{}`", ty),
+              span.clone(),
+            )
+            .with_label(expr.span(), format!("this is a `{}`", ty)),
+          );
+        }
+        Ok(ty)
+      }
+      Expr::Binary { op, lhs, rhs } if op.is_logical() => {
+        for side in [lhs, rhs] {
+          let found = self.check_expr(side, Some(Type::Bool))?;
+          if found != Type::Bool {
+            return Err(
+              mismatch(side.span(), Type::Bool, found)
+                .with_note(format!("help: `{}` can only combine `bool` values", op)),
+            );
+          }
+        }
+        Ok(Type::Bool)
+      }
+      Expr::Binary { op, lhs, rhs } => {
+        let operand = if op.is_arithmetic() { expected } else { None };
+        // A literal takes on the type of the other side, wherever it is
+        let (lhs_ty, rhs_ty) = if is_number_literal(lhs) && !is_number_literal(rhs) {
+          let rhs_ty = self.check_expr(rhs, operand)?;
+          (self.check_expr(lhs, Some(rhs_ty))?, rhs_ty)
+        } else {
+          let lhs_ty = self.check_expr(lhs, operand)?;
+          (lhs_ty, self.check_expr(rhs, Some(lhs_ty))?)
+        };
+        let span = lhs.span().start..rhs.span().end;
+        if lhs_ty != rhs_ty {
+          return Err(
+            Diagnostic::error(
+              ErrorCode::TypeMismatch,
+              format!("cannot use `{}` on a `{}` and a `{}`", op, lhs_ty, rhs_ty),
+              span,
+            )
+            .with_label(lhs.span(), format!("this is a `{}`", lhs_ty))
+            .with_label(rhs.span(), format!("this is a `{}`", rhs_ty)),
+          );
+        }
+        if op.is_comparison() {
+          return Ok(Type::Bool);
+        }
+        // Strings can be joined together with `+`
+        if lhs_ty == Type::String && *op == BinOp::Add {
+          return Ok(Type::String);
+        }
+        if !lhs_ty.is_numeric() || (*op == BinOp::Rem && lhs_ty.is_float()) {
+          return Err(unsupported(*op, lhs_ty, span));
+        }
+        Ok(lhs_ty)
+      }
+    }
+  }
+}
+
+/// Is the `Expr` a number written directly in the source, possibly negated
+fn is_number_literal(expr: &Expr) -> bool {
+  match expr {
+    Expr::Literal { value, .. } => value.ty().is_numeric(),
+    Expr::Unary {
+      op: UnaryOp::Neg,
+      expr,
+      ..
+    } => is_number_literal(expr),
+    _ => false,
+  }
+}
+
+/// Create the error for a value that is not of the expected type
+fn mismatch(span: Range<usize>, expected: Type, found: Type) -> Diagnostic {
+  Diagnostic::error(ErrorCode::TypeMismatch, "mismatched types", span.clone())
+    .with_label(span, format!("expected `{}`, found `{}`", expected, found))
+}
+
+/// Create the error for an operator used on a type it doesn't work with
+fn unsupported(op: BinOp, ty: Type, span: Range<usize>) -> Diagnostic {
+  Diagnostic::error(
+    ErrorCode::TypeMismatch,
+    format!("cannot use `{}` on values of type `{}`", op, ty),
+    span.clone(),
+  )
+  .with_label(span, format!("these are `{}` values", ty))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{parser::SycParser, types::SycValue};
+
+  /// Type check a program that parses cleanly, returning it with its types
+  /// filled in along with the codes of the problems found
+  fn check(source: &str) -> (Vec<Statement>, Vec<ErrorCode>) {
+    let (mut program, errors) = SycParser::new(source).parse();
+    assert!(errors.is_empty(), "{:?}", errors);
+    let errors = TypeChecker::new(&program).check(&mut program);
+    (program, errors.into_iter().map(|e| e.code).collect())
+  }
+
+  /// Type check statements as the body of `main`, returning the checked body
+  fn check_main(body: &str) -> (Vec<Statement>, Vec<ErrorCode>) {
+    let (program, errors) = check(&format!("terminating state main() {{ {} }}", body));
+    match program.into_iter().next() {
+      Some(Statement::StateDefn { statements, .. }) => (statements, errors),
+      _ => unreachable!("the program is a single state"),
+    }
+  }
+
+  /// The types of the variables assigned to in a block, in order
+  fn assigned_types(statements: &[Statement]) -> Vec<Option<Type>> {
+    statements
+      .iter()
+      .filter_map(|stmt| match stmt {
+        Statement::Assignment { ty, .. } => Some(*ty),
+        _ => None,
+      })
+      .collect()
+  }
+
+  #[test]
+  fn variables_take_the_type_of_their_first_value() {
+    let (statements, errors) =
+      check_main(r#"a <- 1; b <- 5000000000; c <- 1.5; d <- true; e <- "hi";"#);
+    assert_eq!(errors, Vec::new());
+    assert_eq!(
+      assigned_types(&statements),
+      vec![
+        Some(Type::I32),
+        Some(Type::I64),
+        Some(Type::F64),
+        Some(Type::Bool),
+        Some(Type::String)
+      ]
+    );
+  }
+
+  #[test]
+  fn literals_take_the_type_they_are_used_as() {
+    let (statements, errors) = check_main("x: i64 <- 5; y <- 2 * x; z: f32 <- 1.5;");
+    assert_eq!(errors, Vec::new());
+    assert_eq!(
+      assigned_types(&statements),
+      vec![Some(Type::I64), Some(Type::I64), Some(Type::F32)]
+    );
+    match &statements[0] {
+      Statement::Assignment {
+        value: Expr::Literal { value, .. },
+        ..
+      } => assert!(matches!(value, SycValue::I64(5))),
+      stmt => panic!("expected an assignment of a literal, found {:?}", stmt),
+    }
+  }
+
+  #[test]
+  fn mismatched_types_are_errors() {
+    for body in [
+      "x <- 1; x <- true;",
+      "x: bool <- 1;",
+      "x <- 1 + true;",
+      "x <- len(5);",
+      "x <- 1.5 % 2.0;",
+      r#"if 1 { println("one"); }"#,
+      "terminate true;",
+    ] {
+      assert_eq!(
+        check_main(body).1,
+        vec![ErrorCode::TypeMismatch],
+        "{}",
+        body
+      );
+    }
+  }
+
+  #[test]
+  fn state_arguments_are_checked() {
+    let (_, errors) = check(
+      r#"
+      terminating state main() { retry(1, 2); }
+      state retry(n: i64) {}
+      "#,
+    );
+    assert_eq!(errors, vec![ErrorCode::ArityMismatch]);
+    let (_, errors) = check(
+      r#"
+      terminating state main() { retry(true); }
+      state retry(n: i64) {}
+      "#,
+    );
+    assert_eq!(errors, vec![ErrorCode::TypeMismatch]);
+  }
+}
@@ -1,8 +1,11 @@
 use crate::{
-  codegen::{Codegen, Generate},
-  wasi::Wasi,
+  builtins::{Builtin, Builtins},
+  codegen::{Codegen, Generate, Local, STATE_RETURN},
+  diagnostics::Diagnostic,
+  error::ErrorCode,
+  wasi::{Wasi, WasiFns},
 };
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt, ops::Range};
 use wasm_encoder::*;
 
 /// A `Statement` is the top level item in a sycamore program. It can define
@@ -11,123 +14,607 @@ use wasm_encoder::*;
 /// in order for code to be generated for it
 #[derive(Debug, Clone)]
 pub enum Statement {
-  /// Assigns a value to an `Ident`
-  Assignment { name: Ident, value: SycValue },
-  /// Defines a state for the program
+  /// Assigns the value of an expression to an `Ident`. The type is filled in
+  /// by the type checker if it was not annotated.
+  Assignment {
+    name: Ident,
+    ty: Option<Type>,
+    value: Expr,
+  },
+  /// Defines a state for the program. `docs` holds the lines of the `///`
+  /// comments written above it.
   StateDefn {
+    docs: Vec<String>,
     terminating: bool,
     name: Ident,
-    input: Vec<Type>,
+    input: Vec<Param>,
     statements: Vec<Statement>,
   },
-  /// Makes a function call for a program
-  FnCall { name: Ident, input: Vec<Type> },
+  /// Makes a function call for a program. If the called state transitions to
+  /// another state the calling state transitions along with it
+  FnCall { name: Ident, input: Vec<Expr> },
+  /// Leaves the current state and moves the state machine to the given state
+  Transition { name: Ident, input: Vec<Expr> },
+  /// Runs one of two blocks depending on the condition
+  If {
+    condition: Expr,
+    then_block: Vec<Statement>,
+    else_block: Vec<Statement>,
+  },
   /// Makes a WASI function call
   Wasi(Wasi),
-  /// Terminates the program
-  Terminate,
+  /// Terminates the program with the given exit code, or 0 if there is none
+  Terminate {
+    code: Option<Expr>,
+    span: Range<usize>,
+  },
 }
 
 impl Generate for Statement {
-  fn generate(&self, codegen: &mut Codegen) {
+  fn generate(&self, codegen: &mut Codegen) -> Result<(), Diagnostic> {
     match self {
+      Statement::StateDefn { name, .. } if codegen.current_func.is_some() => {
+        return Err(
+          Diagnostic::error(
+            ErrorCode::NestedState,
+            "cannot define states inside a state",
+            name.span(),
+          )
+          .with_label(name.span(), "state defined here"),
+        )
+      }
       Statement::StateDefn {
         name,
-        terminating,
         input,
         statements,
+        ..
       } => {
-        let function_num = *codegen.fn_map.get(name.as_str()).unwrap();
-
-        if name.as_str() == "main" {
-          if !terminating {
-            panic!("Main must be labelled an end state");
-          }
-          if !input.is_empty() {
-            panic!("Main must have no arguments");
+        let function_num = match codegen.fn_map.get(name.as_str()) {
+          Some(function_num) => *function_num,
+          None => {
+            return Err(Diagnostic::error(
+              ErrorCode::InternalError,
+              format!("state `{}` was never registered", name.as_str()),
+              name.span(),
+            ))
           }
-          codegen.functions.function(function_num);
-          codegen
-            .exports
-            .export("_start", Export::Function(function_num));
-        } else {
-          codegen.functions.function(function_num);
-        }
+        };
 
-        let mut locals = Vec::new();
-        let mut locals_map = HashMap::new();
+        codegen.functions.function(function_num);
 
-        // Create all the locals to be declared in the function
-        for stmt in statements {
-          if let Statement::Assignment { value, name } = stmt {
-            locals_map.insert(name.as_str(), locals_map.len() as u32);
-            locals.push(value.as_val_type());
-          }
+        // Parameters are the first locals of the function, followed by all
+        // the locals to be declared in the function
+        let mut locals = Vec::new();
+        codegen.locals.clear();
+        for (idx, param) in input.iter().enumerate() {
+          codegen.locals.insert(
+            param.name.as_str().to_string(),
+            Local {
+              idx: idx as u32,
+              ty: param.ty,
+            },
+          );
         }
+        declare_locals(statements, &mut locals, &mut codegen.locals)?;
+        // Scratch local to hold the next state returned by a called state
+        codegen.next_state_local = codegen.locals.len() as u32;
+        locals.push(ValType::I32);
         codegen.current_func = Some(Function::new_with_locals_types(locals));
 
         for stmt in statements {
-          match stmt {
-            Statement::Assignment { name, value } => {
-              let local = locals_map
-                .get(name.as_str())
-                .expect("locals_map was already populated");
-              match value {
-                SycValue::I32(v) => {
-                  codegen.instruction(Instruction::I32Const(*v));
-                }
-              }
-              codegen.instruction(Instruction::LocalSet(*local));
-            }
-            Statement::Terminate => {
-              // TODO: actually do something with this
-            }
-            Statement::Wasi(wasi) => wasi.generate(codegen),
-            Statement::FnCall { name, .. } => {
-              codegen.instruction(Instruction::Call(
-                *codegen.fn_map.get(name.as_str()).unwrap() as u32,
-              ));
-            }
-            Statement::StateDefn { .. } => panic!("Cannot define states inside a state"),
-          }
+          stmt.generate(codegen)?;
         }
+        // Falling off the end of a state returns to whoever called it
+        codegen.instruction(Instruction::I32Const(STATE_RETURN));
         codegen.instruction(Instruction::End);
 
+        let mut locals_names: Vec<_> = codegen.locals.iter().collect();
+        locals_names.sort_by_key(|(_, local)| local.idx);
         let mut local_names = NameMap::new();
-        for (name, idx) in locals_map.iter() {
-          local_names.append(*idx, name);
+        for (name, local) in locals_names {
+          local_names.append(local.idx, name);
         }
+        local_names.append(codegen.next_state_local, "next-state");
         codegen.name.local_names.append(function_num, &local_names);
         codegen
           .codes
           .function(&codegen.current_func.take().unwrap());
       }
-      _ => panic!("Invalid only StateDefn are allowed"),
+      Statement::Assignment { name, value, .. } => {
+        let local = match codegen.locals.get(name.as_str()) {
+          Some(local) => local.idx,
+          None => return Err(untyped(name)),
+        };
+        value.generate(codegen)?;
+        codegen.instruction(Instruction::LocalSet(local));
+      }
+      Statement::Terminate { code, .. } => {
+        match code {
+          Some(code) => code.generate(codegen)?,
+          None => codegen.instruction(Instruction::I32Const(0)),
+        }
+        codegen.proc_exit();
+      }
+      Statement::Wasi(wasi) => wasi.generate(codegen)?,
+      Statement::FnCall { name, input } => {
+        let function_num = codegen.state_index(name)?;
+        for arg in input {
+          arg.generate(codegen)?;
+        }
+        let next_state = codegen.next_state_local;
+        codegen.instruction(Instruction::Call(function_num));
+        // If the called state transitioned somewhere we stop executing this
+        // state and pass the transition on to whoever called us
+        codegen.instruction(Instruction::LocalTee(next_state));
+        codegen.instruction(Instruction::I32Const(STATE_RETURN));
+        codegen.instruction(Instruction::I32Ne);
+        codegen.instruction(Instruction::If(BlockType::Empty));
+        codegen.instruction(Instruction::LocalGet(next_state));
+        codegen.instruction(Instruction::Return);
+        codegen.instruction(Instruction::End);
+      }
+      Statement::Transition { name, input } => {
+        let function_num = codegen.state_index(name)?;
+        // The dispatcher passes the arguments on from the state's globals
+        for arg in input {
+          arg.generate(codegen)?;
+        }
+        let globals = codegen.state_globals[name.as_str()].clone();
+        for global in globals.into_iter().rev() {
+          codegen.instruction(Instruction::GlobalSet(global));
+        }
+        codegen.instruction(Instruction::I32Const(function_num as i32));
+        codegen.instruction(Instruction::Return);
+      }
+      Statement::If {
+        condition,
+        then_block,
+        else_block,
+      } => {
+        condition.generate(codegen)?;
+        codegen.instruction(Instruction::If(BlockType::Empty));
+        for stmt in then_block {
+          stmt.generate(codegen)?;
+        }
+        if !else_block.is_empty() {
+          codegen.instruction(Instruction::Else);
+          for stmt in else_block {
+            stmt.generate(codegen)?;
+          }
+        }
+        codegen.instruction(Instruction::End);
+      }
     }
+    Ok(())
   }
 }
 
-#[allow(dead_code)]
+/// Declare a local for every variable assigned to in the given statements,
+/// including those in nested blocks. Variables already in `locals_map` reuse
+/// their local.
+fn declare_locals(
+  statements: &[Statement],
+  locals: &mut Vec<ValType>,
+  locals_map: &mut HashMap<String, Local>,
+) -> Result<(), Diagnostic> {
+  for stmt in statements {
+    match stmt {
+      Statement::Assignment { name, ty, .. } => {
+        if !locals_map.contains_key(name.as_str()) {
+          let ty = ty.ok_or_else(|| untyped(name))?;
+          let idx = locals_map.len() as u32;
+          locals_map.insert(name.as_str().to_string(), Local { idx, ty });
+          locals.push(ty.val_type());
+        }
+      }
+      Statement::If {
+        then_block,
+        else_block,
+        ..
+      } => {
+        declare_locals(then_block, locals, locals_map)?;
+        declare_locals(else_block, locals, locals_map)?;
+      }
+      _ => (),
+    }
+  }
+  Ok(())
+}
+
+/// Create the error for a variable with no type, which happens when code is
+/// generated for a program that was not type checked
+fn untyped(name: &Ident) -> Diagnostic {
+  Diagnostic::error(
+    ErrorCode::InternalError,
+    format!("the type of `{}` is not known", name.as_str()),
+    name.span(),
+  )
+  .with_label(name.span(), "this variable has no type")
+  .with_note("the program has to be type checked before code is generated for it")
+}
+
+/// An expression that evaluates to a value
 #[derive(Debug, Clone)]
+pub enum Expr {
+  /// A value written directly in the source
+  Literal { value: SycValue, span: Range<usize> },
+  /// Reads the current value of a variable
+  Variable(Ident),
+  /// Applies an operator to the result of an expression
+  Unary {
+    op: UnaryOp,
+    expr: Box<Expr>,
+    span: Range<usize>,
+  },
+  /// Applies an operator to the result of two expressions
+  Binary {
+    op: BinOp,
+    lhs: Box<Expr>,
+    rhs: Box<Expr>,
+  },
+}
+
+impl Expr {
+  /// Get the span of the source code the `Expr` covers
+  pub fn span(&self) -> Range<usize> {
+    match self {
+      Expr::Literal { span, .. } | Expr::Unary { span, .. } => span.clone(),
+      Expr::Variable(name) => name.span(),
+      Expr::Binary { lhs, rhs, .. } => lhs.span().start..rhs.span().end,
+    }
+  }
+
+  /// Get the type of the value the `Expr` evaluates to in the function code
+  /// is being generated for. This fails if the program was not type checked.
+  pub(crate) fn ty(&self, codegen: &Codegen) -> Result<Type, Diagnostic> {
+    Ok(match self {
+      Expr::Literal { value, .. } => value.ty(),
+      Expr::Variable(name) => match codegen.locals.get(name.as_str()) {
+        Some(local) => local.ty,
+        None => return Err(untyped(name)),
+      },
+      Expr::Unary {
+        op: UnaryOp::Len, ..
+      } => Type::I32,
+      Expr::Unary { expr, .. } => expr.ty(codegen)?,
+      Expr::Binary { op, lhs, .. } if op.is_arithmetic() => lhs.ty(codegen)?,
+      Expr::Binary { .. } => Type::Bool,
+    })
+  }
+
+  /// Does the `Expr` make a new string on the heap that nothing else points
+  /// to, which has to be freed by whatever uses it
+  pub(crate) fn is_temporary(&self, codegen: &Codegen) -> Result<bool, Diagnostic> {
+    match self {
+      Expr::Binary {
+        op: BinOp::Add,
+        lhs,
+        ..
+      } => Ok(lhs.ty(codegen)? == Type::String),
+      _ => Ok(false),
+    }
+  }
+
+  /// Create instructions to get the length of the string the `Expr`
+  /// evaluates to. The lengths of joined strings are added up instead of
+  /// joining them.
+  fn generate_len(&self, codegen: &mut Codegen) -> Result<(), Diagnostic> {
+    match self {
+      Expr::Binary {
+        op: BinOp::Add,
+        lhs,
+        rhs,
+      } => {
+        lhs.generate_len(codegen)?;
+        rhs.generate_len(codegen)?;
+        codegen.instruction(Instruction::I32Add);
+      }
+      _ => {
+        // The length is in the high 32 bits
+        self.generate(codegen)?;
+        codegen.instruction(Instruction::I64Const(32));
+        codegen.instruction(Instruction::I64ShrU);
+        codegen.instruction(Instruction::I32WrapI64);
+      }
+    }
+    Ok(())
+  }
+}
+
+impl Generate for Expr {
+  fn generate(&self, codegen: &mut Codegen) -> Result<(), Diagnostic> {
+    match self {
+      Expr::Literal { value, .. } => codegen.instruction(match value {
+        SycValue::I32(v) => Instruction::I32Const(*v),
+        SycValue::I64(v) => Instruction::I64Const(*v),
+        SycValue::F32(v) => Instruction::F32Const(*v),
+        SycValue::F64(v) => Instruction::F64Const(*v),
+        SycValue::Bool(v) => Instruction::I32Const(*v as i32),
+        SycValue::Str(s) => {
+          let ptr = codegen.add_literal(s.as_str()) as i64;
+          Instruction::I64Const(ptr | (s.len() as i64) << 32)
+        }
+      }),
+      Expr::Variable(name) => {
+        let local = match codegen.locals.get(name.as_str()) {
+          Some(local) => local.idx,
+          None => {
+            return Err(
+              Diagnostic::error(
+                ErrorCode::UnknownVariable,
+                format!("no variable named `{}`", name.as_str()),
+                name.span(),
+              )
+              .with_label(name.span(), "this variable is never assigned in this state"),
+            )
+          }
+        };
+        codegen.instruction(Instruction::LocalGet(local));
+      }
+      Expr::Unary {
+        op: UnaryOp::Len,
+        expr,
+        ..
+      } => expr.generate_len(codegen)?,
+      Expr::Unary {
+        op: UnaryOp::Neg,
+        expr,
+        ..
+      } => match expr.ty(codegen)? {
+        Type::F32 => {
+          expr.generate(codegen)?;
+          codegen.instruction(Instruction::F32Neg);
+        }
+        Type::F64 => {
+          expr.generate(codegen)?;
+          codegen.instruction(Instruction::F64Neg);
+        }
+        Type::I64 => {
+          codegen.instruction(Instruction::I64Const(0));
+          expr.generate(codegen)?;
+          codegen.instruction(Instruction::I64Sub);
+        }
+        _ => {
+          codegen.instruction(Instruction::I32Const(0));
+          expr.generate(codegen)?;
+          codegen.instruction(Instruction::I32Sub);
+        }
+      },
+      Expr::Binary { op, lhs, rhs } => {
+        // Both sides have the same type after type checking
+        let ty = lhs.ty(codegen)?;
+        lhs.generate(codegen)?;
+        rhs.generate(codegen)?;
+        let instruction = match (op, ty) {
+          (BinOp::Add | BinOp::Equals, Type::String) => {
+            // The builtin frees the strings that were only made for it
+            let free_lhs = lhs.is_temporary(codegen)?;
+            let free_rhs = rhs.is_temporary(codegen)?;
+            codegen.instruction(Instruction::I32Const(free_lhs as i32));
+            codegen.instruction(Instruction::I32Const(free_rhs as i32));
+            let builtin = match op {
+              BinOp::Add => Builtin::StrConcat,
+              _ => Builtin::StrEq,
+            };
+            Instruction::Call(codegen.builtin(builtin))
+          }
+          _ => op.instruction(ty),
+        };
+        codegen.instruction(instruction);
+      }
+    }
+    Ok(())
+  }
+}
+
+/// An operator that combines two expressions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinOp {
+  Equals,
+  And,
+  Or,
+  Add,
+  Sub,
+  Mul,
+  Div,
+  Rem,
+}
+
+impl BinOp {
+  /// How tightly the operator binds. Operators with a higher precedence are
+  /// applied first.
+  pub fn precedence(&self) -> u8 {
+    match self {
+      BinOp::Or => 1,
+      BinOp::And => 2,
+      BinOp::Equals => 3,
+      BinOp::Add | BinOp::Sub => 4,
+      BinOp::Mul | BinOp::Div | BinOp::Rem => 5,
+    }
+  }
+
+  /// Is the operator `+`, `-`, `*`, `/` or `%`
+  pub fn is_arithmetic(&self) -> bool {
+    matches!(
+      self,
+      BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div | BinOp::Rem
+    )
+  }
+
+  /// Does the operator compare its operands
+  pub fn is_comparison(&self) -> bool {
+    matches!(self, BinOp::Equals)
+  }
+
+  /// Is the operator `and` or `or`
+  pub fn is_logical(&self) -> bool {
+    matches!(self, BinOp::And | BinOp::Or)
+  }
+
+  /// Get the instruction for the operator applied to operands of type `ty`.
+  /// Booleans are always 0 or 1 so `and` and `or` work bitwise.
+  fn instruction(&self, ty: Type) -> Instruction<'static> {
+    match (self, ty) {
+      (BinOp::And, _) => Instruction::I32And,
+      (BinOp::Or, _) => Instruction::I32Or,
+      (BinOp::Equals, Type::I64) => Instruction::I64Eq,
+      (BinOp::Equals, Type::F32) => Instruction::F32Eq,
+      (BinOp::Equals, Type::F64) => Instruction::F64Eq,
+      (BinOp::Equals, _) => Instruction::I32Eq,
+      (BinOp::Add, Type::I64) => Instruction::I64Add,
+      (BinOp::Add, Type::F32) => Instruction::F32Add,
+      (BinOp::Add, Type::F64) => Instruction::F64Add,
+      (BinOp::Add, _) => Instruction::I32Add,
+      (BinOp::Sub, Type::I64) => Instruction::I64Sub,
+      (BinOp::Sub, Type::F32) => Instruction::F32Sub,
+      (BinOp::Sub, Type::F64) => Instruction::F64Sub,
+      (BinOp::Sub, _) => Instruction::I32Sub,
+      (BinOp::Mul, Type::I64) => Instruction::I64Mul,
+      (BinOp::Mul, Type::F32) => Instruction::F32Mul,
+      (BinOp::Mul, Type::F64) => Instruction::F64Mul,
+      (BinOp::Mul, _) => Instruction::I32Mul,
+      (BinOp::Div, Type::I64) => Instruction::I64DivS,
+      (BinOp::Div, Type::F32) => Instruction::F32Div,
+      (BinOp::Div, Type::F64) => Instruction::F64Div,
+      (BinOp::Div, _) => Instruction::I32DivS,
+      (BinOp::Rem, Type::I64) => Instruction::I64RemS,
+      (BinOp::Rem, _) => Instruction::I32RemS,
+    }
+  }
+}
+
+impl fmt::Display for BinOp {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let op = match self {
+      BinOp::Equals => "equals",
+      BinOp::And => "and",
+      BinOp::Or => "or",
+      BinOp::Add => "+",
+      BinOp::Sub => "-",
+      BinOp::Mul => "*",
+      BinOp::Div => "/",
+      BinOp::Rem => "%",
+    };
+    write!(f, "{}", op)
+  }
+}
+
+/// The type of a value in a sycamore program
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Type {
   I32,
+  I64,
+  F32,
+  F64,
+  Bool,
+  /// Text stored in memory. The value is an i64 with the pointer to the text
+  /// in the low 32 bits and its length in the high 32 bits, which is the same
+  /// layout as a WASI iovec.
+  String,
 }
 
-/// An identifier for a state, variable, or something else
-#[derive(Debug, PartialEq, Eq, Clone)]
-pub struct Ident(String);
+impl Type {
+  /// Get the `Type` with the given name in the source code
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "i32" => Some(Type::I32),
+      "i64" => Some(Type::I64),
+      "f32" => Some(Type::F32),
+      "f64" => Some(Type::F64),
+      "bool" => Some(Type::Bool),
+      "string" => Some(Type::String),
+      _ => None,
+    }
+  }
+
+  /// Get the wasm type values of this `Type` are stored as
+  pub(crate) fn val_type(&self) -> ValType {
+    match self {
+      Type::I32 | Type::Bool => ValType::I32,
+      Type::I64 | Type::String => ValType::I64,
+      Type::F32 => ValType::F32,
+      Type::F64 => ValType::F64,
+    }
+  }
+
+  /// Get an instruction that creates the zero value of this `Type`
+  pub(crate) fn zero(&self) -> Instruction<'static> {
+    match self {
+      Type::I32 | Type::Bool => Instruction::I32Const(0),
+      // The zero string is empty
+      Type::I64 | Type::String => Instruction::I64Const(0),
+      Type::F32 => Instruction::F32Const(0.0),
+      Type::F64 => Instruction::F64Const(0.0),
+    }
+  }
+
+  /// Can values of this `Type` be used in arithmetic
+  pub fn is_numeric(&self) -> bool {
+    !matches!(self, Type::Bool | Type::String)
+  }
+
+  /// Is this `Type` a floating point number
+  pub fn is_float(&self) -> bool {
+    matches!(self, Type::F32 | Type::F64)
+  }
+}
+
+impl fmt::Display for Type {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let name = match self {
+      Type::I32 => "i32",
+      Type::I64 => "i64",
+      Type::F32 => "f32",
+      Type::F64 => "f64",
+      Type::Bool => "bool",
+      Type::String => "string",
+    };
+    write!(f, "{}", name)
+  }
+}
+
+/// A parameter of a state
+#[derive(Debug, Clone)]
+pub struct Param {
+  pub name: Ident,
+  pub ty: Type,
+}
+
+/// An identifier for a state, variable, or something else. Two `Ident`s are
+/// equal if they have the same name, regardless of where in the source they
+/// are.
+#[derive(Debug, Clone)]
+pub struct Ident {
+  name: String,
+  span: Range<usize>,
+}
 
 impl Ident {
   /// Create a new `Ident`
-  pub fn new(input: impl ToString) -> Self {
-    Self(input.to_string())
+  pub fn new(input: impl ToString, span: Range<usize>) -> Self {
+    Self {
+      name: input.to_string(),
+      span,
+    }
   }
   /// Get an `&str` of the `Ident`
   pub fn as_str(&self) -> &str {
-    &self.0
+    &self.name
+  }
+  /// Get the span of the source code the `Ident` was defined at
+  pub fn span(&self) -> Range<usize> {
+    self.span.clone()
   }
 }
 
+impl PartialEq for Ident {
+  fn eq(&self, other: &Self) -> bool {
+    self.name == other.name
+  }
+}
+
+impl Eq for Ident {}
+
 /// A string literal defined in the source code
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct StrLit(String);
@@ -141,27 +628,74 @@ impl StrLit {
   pub fn as_str(&self) -> &str {
     &self.0
   }
-  /// Get the length of the `StrLit`
+  /// Get the length of the `StrLit` in bytes once encoded as UTF-8
   pub fn len(&self) -> usize {
     self.0.len()
   }
+  /// Is the `StrLit` an empty string
+  pub fn is_empty(&self) -> bool {
+    self.0.is_empty()
+  }
+}
+
+/// An operator applied to a single expression
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+  /// Negates a number, `-x`
+  Neg,
+  /// The length of a string in bytes, `len(x)`
+  Len,
 }
 
 /// A sycamore program type and the value of said type
 #[derive(Debug, Clone)]
 pub enum SycValue {
   I32(i32),
+  I64(i64),
+  F32(f32),
+  F64(f64),
+  Bool(bool),
+  Str(StrLit),
 }
 
 impl SycValue {
-  #[allow(dead_code)]
-  pub fn from_i32(input: i32) -> Self {
-    Self::I32(input)
+  /// Get the `Type` of the value
+  pub fn ty(&self) -> Type {
+    match self {
+      Self::I32(_) => Type::I32,
+      Self::I64(_) => Type::I64,
+      Self::F32(_) => Type::F32,
+      Self::F64(_) => Type::F64,
+      Self::Bool(_) => Type::Bool,
+      Self::Str(_) => Type::String,
+    }
   }
 
-  pub fn as_val_type(&self) -> ValType {
-    match self {
-      Self::I32(_) => ValType::I32,
+  /// Convert a number to the same number of type `ty` if it fits
+  pub fn coerce(&self, ty: Type) -> Option<Self> {
+    match (self, ty) {
+      (Self::I32(v), Type::I64) => Some(Self::I64(*v as i64)),
+      (Self::I64(v), Type::I32) => i32::try_from(*v).ok().map(Self::I32),
+      (Self::F64(v), Type::F32) => Some(Self::F32(*v as f32)),
+      (Self::F32(v), Type::F64) => Some(Self::F64(*v as f64)),
+      _ => None,
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use crate::{codegen::Codegen, compiler::Target, error::ErrorCode, parser::SycParser};
+
+  #[test]
+  fn unchecked_programs_are_internal_errors() {
+    let source = r#"terminating state main() { x <- 1; println("{x}"); }"#;
+    let (program, errors) = SycParser::new(source).parse();
+    assert!(errors.is_empty(), "{:?}", errors);
+    let error = Codegen::new(program, false, Target::default())
+      .generate()
+      .unwrap_err();
+    assert_eq!(error.code, ErrorCode::InternalError);
+    assert_eq!(&source[error.span], "x");
+  }
+}
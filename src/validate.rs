@@ -0,0 +1,85 @@
+use wasmparser::{
+  ImportSectionEntryType, Name, NameSectionReader, Parser, Payload, Validator, WasmFeatures,
+};
+
+/// A problem `wasmparser` found in a module that was generated by the
+/// compiler
+#[derive(Debug, Clone)]
+pub struct InvalidModule {
+  /// What is wrong with the module
+  pub message: String,
+  /// The byte offset in the module the problem was found at
+  pub offset: usize,
+  /// The name of the function the problem is in, if it is in one and the
+  /// module names it
+  pub function: Option<String>,
+}
+
+/// Check that a module is valid WebAssembly
+pub fn validate(wasm: &[u8]) -> Result<(), InvalidModule> {
+  let mut validator = Validator::new();
+  validator.wasm_features(WasmFeatures {
+    bulk_memory: true,
+    ..WasmFeatures::default()
+  });
+  validator.validate_all(wasm).map_err(|e| InvalidModule {
+    message: e.message().to_string(),
+    offset: e.offset(),
+    function: function_at(wasm, e.offset()),
+  })
+}
+
+/// Find the name of the function whose body contains `offset` using the
+/// name section of the module
+fn function_at(wasm: &[u8], offset: usize) -> Option<String> {
+  let mut imported = 0;
+  let mut defined = 0;
+  let mut found = None;
+  let mut names = Vec::new();
+  for payload in Parser::new(0).parse_all(wasm) {
+    match payload.ok()? {
+      Payload::ImportSection(reader) => {
+        for import in reader {
+          if let ImportSectionEntryType::Function(_) = import.ok()?.ty {
+            imported += 1;
+          }
+        }
+      }
+      Payload::CodeSectionEntry(body) => {
+        let range = body.range();
+        if range.start <= offset && offset < range.end {
+          found = Some(imported + defined);
+        }
+        defined += 1;
+      }
+      Payload::CustomSection {
+        name: "name",
+        data,
+        data_offset,
+        ..
+      } => names = function_names(data, data_offset).unwrap_or_default(),
+      _ => (),
+    }
+  }
+  let found = found?;
+  names
+    .into_iter()
+    .find(|(idx, _)| *idx == found)
+    .map(|(_, name)| name)
+}
+
+/// Read the names of the functions out of a name section
+fn function_names(data: &[u8], offset: usize) -> wasmparser::Result<Vec<(u32, String)>> {
+  let mut reader = NameSectionReader::new(data, offset)?;
+  let mut names = Vec::new();
+  while !reader.eof() {
+    if let Name::Function(functions) = reader.read()? {
+      let mut map = functions.get_map()?;
+      for _ in 0..map.get_count() {
+        let naming = map.read()?;
+        names.push((naming.index, naming.name.to_string()));
+      }
+    }
+  }
+  Ok(names)
+}
@@ -1,76 +1,89 @@
 use crate::{
-  codegen::{Codegen, Generate, MAX_MEM, RESULT_IDX},
+  builtins::{Builtin, Builtins, INT_MAX_DIGITS},
+  codegen::{Codegen, Generate},
   context::SycContext,
-  types::StrLit,
+  diagnostics::Diagnostic,
+  error::SycError,
+  memory::{align, mem_arg, Memory, STACK_POINTER},
+  types::{Expr, StrLit, Type},
 };
-use std::error::Error;
 use wasm_encoder::*;
 use wasmtime::Linker;
 
 /// Put the wasi functions to link inside the [`wasmtime::Linker`]
-pub fn wasi_linker(linker: &mut Linker<SycContext>) -> Result<(), Box<dyn Error>> {
+pub fn wasi_linker(linker: &mut Linker<SycContext>) -> Result<(), SycError> {
   wasmtime_wasi::add_to_linker(linker, |state: &mut SycContext| {
     state.wasi.as_mut().unwrap()
-  })?;
-  Ok(())
+  })
+  .map_err(|e| SycError::Link(e.into()))
 }
 
 #[derive(Debug, Clone)]
 /// Enum of types of Input and Output that `sycamore` can do
 pub enum Wasi {
-  /// Print a given string literal
-  Print(StrLit),
-  /// Print a given string literal with a newline
-  Println(StrLit),
+  /// Print the given parts one after another
+  Print(Vec<PrintArg>),
+  /// Print the given parts one after another followed by a newline
+  Println(Vec<PrintArg>),
+}
+
+#[derive(Debug, Clone)]
+/// A part of what gets printed by a `Wasi::Print` or `Wasi::Println`
+pub enum PrintArg {
+  /// Text that is known at compile time
+  Str(StrLit),
+  /// A value that gets formatted when the program runs
+  Expr(Expr),
 }
 
 impl Wasi {
   /// Is this WASI item a call to `Println`
   fn is_println(&self) -> bool {
-    match self {
-      Wasi::Println(_) => true,
-      _ => false,
-    }
+    matches!(self, Wasi::Println(_))
   }
 }
 
 impl Generate for Wasi {
   /// Generate code for WASI statements
-  fn generate(&self, codegen: &mut Codegen) {
+  fn generate(&self, codegen: &mut Codegen) -> Result<(), Diagnostic> {
     match self {
-      Wasi::Print(literal) | Wasi::Println(literal) => {
-        // Find where the string should be written into in memory
-        let offset = {
-          let mut offset = 0;
-          for lit in &codegen.literal_table {
-            offset += lit.len();
-          }
-          offset as i32
-        };
-
-        // Write the data into memory
-        codegen
-          .data
-          .active(0, &Instruction::I32Const(offset), literal.as_str().bytes());
-
+      Wasi::Print(args) | Wasi::Println(args) => {
         // Setup pointers to the data to be printed out
         let mut io_vec = Vec::new();
-        io_vec.push(IoVecItem::new(offset, literal.len() as i32));
+        for arg in args {
+          match arg {
+            PrintArg::Str(literal) => {
+              let offset = codegen.add_literal(literal.as_str());
+              io_vec.push(IoVecItem::new(offset, literal.len() as i32));
+            }
+            PrintArg::Expr(expr) => match expr.ty(codegen)? {
+              Type::Bool => {
+                // `true` directly followed by `false` so either can be pointed
+                // to
+                let true_offset = codegen.add_literal("truefalse");
+                io_vec.push(IoVecItem::Bool {
+                  expr: expr.clone(),
+                  true_offset,
+                });
+              }
+              Type::String => io_vec.push(IoVecItem::Str(expr.clone())),
+              _ => io_vec.push(IoVecItem::Int(expr.clone())),
+            },
+          }
+        }
 
         // If we are calling println point to the newline character in the
         // binary
         if self.is_println() {
-          io_vec.push(IoVecItem::new(0, 1));
+          let offset = codegen.add_literal("\n");
+          io_vec.push(IoVecItem::new(offset, 1));
         }
 
         // Create the assmbly for the write to stdout
-        codegen.fd_write(STDOUT, io_vec);
-
-        // Push the literal into the codegen table so we can keep track of
-        // things
-        codegen.literal_table.push(literal.as_str().into());
+        codegen.fd_write(STDOUT, io_vec)?;
       }
     }
+    Ok(())
   }
 }
 
@@ -79,70 +92,157 @@ const STDOUT: i32 = 1;
 
 /// Function helpers for code generation with the `Codegen` type
 pub trait WasiFnHelpers {
-  fn write_io_vec(&mut self, io_vec: Vec<IoVecItem>) -> i32;
+  fn write_io_vec(&mut self, io_vec: Vec<IoVecItem>) -> Result<i32, Diagnostic>;
   fn wasi_imports(&mut self);
 }
 
 /// Function code generation for WASI for use in the `Codegen` type
 pub trait WasiFns {
-  fn fd_write(&mut self, fd: i32, io_vec: Vec<IoVecItem>);
+  fn fd_write(&mut self, fd: i32, io_vec: Vec<IoVecItem>) -> Result<(), Diagnostic>;
+  fn proc_exit(&mut self);
 }
 
 impl WasiFns for Codegen {
   /// Create instructions to write an `iov` to a given file descriptor
-  fn fd_write(&mut self, fd: i32, io_vec: Vec<IoVecItem>) {
+  fn fd_write(&mut self, fd: i32, io_vec: Vec<IoVecItem>) -> Result<(), Diagnostic> {
     let num_strs = io_vec.len() as i32;
-    let io_vec_ptr = self.write_io_vec(io_vec);
+    // The entries for strings that were only made to be written
+    let mut temporaries = Vec::new();
+    for (idx, item) in io_vec.iter().enumerate() {
+      if let IoVecItem::Str(expr) = item {
+        if expr.is_temporary(self)? {
+          temporaries.push(idx as u32);
+        }
+      }
+    }
+    let stack_size = self.write_io_vec(io_vec)?;
+    let stack_pointer = self.global_map[STACK_POINTER];
 
     // Set write to given fd
     self.instruction(Instruction::I32Const(fd));
     // Pointer to array of iov
-    self.instruction(Instruction::I32Const(io_vec_ptr));
+    self.instruction(Instruction::GlobalGet(stack_pointer));
     // Number of strings written
     self.instruction(Instruction::I32Const(num_strs));
-    // Where to store the number of bytes written
-    self.instruction(Instruction::I32Const(RESULT_IDX));
+    // Where to store the number of bytes written, right after the iov
+    self.instruction(Instruction::GlobalGet(stack_pointer));
+    self.instruction(Instruction::I32Const(num_strs * 8));
+    self.instruction(Instruction::I32Add);
     // Call `fd_write`
     self.instruction(Instruction::Call(FD_WRITE));
     // Drop number of bytes written
     self.instruction(Instruction::Drop);
+    // Free the strings that were only made to be written
+    if !temporaries.is_empty() {
+      let free = self.builtin(Builtin::Free);
+      for idx in temporaries {
+        self.instruction(Instruction::GlobalGet(stack_pointer));
+        self.instruction(Instruction::I32Load(mem_arg(idx * 8)));
+        self.instruction(Instruction::Call(free));
+      }
+    }
+    self.stack_free(stack_size);
+    Ok(())
+  }
+
+  /// Create instructions to exit the program with the exit code on top of
+  /// the stack
+  fn proc_exit(&mut self) {
+    self.instruction(Instruction::Call(PROC_EXIT));
+    // `proc_exit` never returns
+    self.instruction(Instruction::Unreachable);
   }
 }
 
 // WASI Function Number Constants
 const FD_WRITE: u32 = 0;
+const PROC_EXIT: u32 = 1;
 
 impl WasiFnHelpers for Codegen {
-  /// Create instructions to write an iov into memory when executing a program
-  fn write_io_vec(&mut self, io_vec: Vec<IoVecItem>) -> i32 {
-    // Find the amount of bytes needed plus a little extra space for
-    // the result
-    let item_len_plus_result = ((io_vec.len() * 8) + 4) as i32;
-    let ptr = MAX_MEM - item_len_plus_result;
-    let mut current_idx = ptr;
+  /// Create instructions to write an iov onto the stack when executing a
+  /// program. Values that are formatted at runtime get written into their own
+  /// buffer after the iov and the number of bytes written. Returns the size of
+  /// the stack allocation, which starts at the stack pointer.
+  fn write_io_vec(&mut self, io_vec: Vec<IoVecItem>) -> Result<i32, Diagnostic> {
+    let num_ints = io_vec
+      .iter()
+      .filter(|item| matches!(item, IoVecItem::Int(_)))
+      .count() as i32;
+    let result_offset = io_vec.len() as i32 * 8;
+    let mut buffer_end = result_offset + 4;
+    let size = align(buffer_end + num_ints * INT_MAX_DIGITS, 16);
+    self.stack_alloc(size);
+    let stack_pointer = self.global_map[STACK_POINTER];
 
     // Write code to store each item from the vec
-    for IoVecItem { offset, len } in io_vec {
-      self.instruction(Instruction::I32Const(current_idx));
-      self.instruction(Instruction::I32Const(offset));
-      self.instruction(Instruction::I32Store(MemArg {
-        memory_index: 0,
-        align: 0,
-        offset: 0,
-      }));
-      current_idx += 4;
-      self.instruction(Instruction::I32Const(current_idx));
-      self.instruction(Instruction::I32Const(len));
-      self.instruction(Instruction::I32Store(MemArg {
-        memory_index: 0,
-        align: 0,
-        offset: 0,
-      }));
-      current_idx += 4;
+    for (idx, item) in io_vec.into_iter().enumerate() {
+      let ptr = mem_arg(idx as u32 * 8);
+      let len = mem_arg(idx as u32 * 8 + 4);
+      match item {
+        IoVecItem::Static {
+          offset,
+          len: item_len,
+        } => {
+          self.instruction(Instruction::GlobalGet(stack_pointer));
+          self.instruction(Instruction::I32Const(offset));
+          self.instruction(Instruction::I32Store(ptr));
+          self.instruction(Instruction::GlobalGet(stack_pointer));
+          self.instruction(Instruction::I32Const(item_len));
+          self.instruction(Instruction::I32Store(len));
+        }
+        IoVecItem::Int(expr) => {
+          // Format the value so that it ends at `buffer_end` and point at
+          // where it starts
+          buffer_end += INT_MAX_DIGITS;
+          self.instruction(Instruction::GlobalGet(stack_pointer));
+          expr.generate(self)?;
+          if expr.ty(self)? == Type::I32 {
+            self.instruction(Instruction::I64ExtendI32S);
+          }
+          self.instruction(Instruction::GlobalGet(stack_pointer));
+          self.instruction(Instruction::I32Const(buffer_end));
+          self.instruction(Instruction::I32Add);
+          let fmt_int = self.builtin(Builtin::FmtInt);
+          self.instruction(Instruction::Call(fmt_int));
+          self.instruction(Instruction::I32Store(ptr));
+          // The length is from where it starts up to `buffer_end`
+          self.instruction(Instruction::GlobalGet(stack_pointer));
+          self.instruction(Instruction::GlobalGet(stack_pointer));
+          self.instruction(Instruction::I32Const(buffer_end));
+          self.instruction(Instruction::I32Add);
+          self.instruction(Instruction::GlobalGet(stack_pointer));
+          self.instruction(Instruction::I32Load(ptr));
+          self.instruction(Instruction::I32Sub);
+          self.instruction(Instruction::I32Store(len));
+        }
+        IoVecItem::Bool { expr, true_offset } => {
+          // Point at `false` right after `true` unless the value is true
+          self.instruction(Instruction::GlobalGet(stack_pointer));
+          self.instruction(Instruction::I32Const(true_offset));
+          self.instruction(Instruction::I32Const(true_offset + 4));
+          expr.generate(self)?;
+          self.instruction(Instruction::Select);
+          self.instruction(Instruction::I32Store(ptr));
+          // `true` is one byte shorter than `false`
+          self.instruction(Instruction::GlobalGet(stack_pointer));
+          self.instruction(Instruction::I32Const(5));
+          self.instruction(Instruction::GlobalGet(stack_pointer));
+          self.instruction(Instruction::I32Load(ptr));
+          self.instruction(Instruction::I32Const(true_offset));
+          self.instruction(Instruction::I32Eq);
+          self.instruction(Instruction::I32Sub);
+          self.instruction(Instruction::I32Store(len));
+        }
+        IoVecItem::Str(expr) => {
+          // A string value is laid out the same as an iov entry
+          self.instruction(Instruction::GlobalGet(stack_pointer));
+          expr.generate(self)?;
+          self.instruction(Instruction::I64Store(ptr));
+        }
+      }
     }
 
-    // Return the location of the vec in memory
-    ptr
+    Ok(size)
   }
 
   /// Import all of the WASI functions for a `sycamore` program
@@ -153,23 +253,38 @@ impl WasiFnHelpers for Codegen {
     );
     self.fn_map.insert("fd_write".into(), FD_WRITE);
     self.imports.import(
-      "wasi_unstable",
+      self.target.wasi_module(),
       Some("fd_write"),
       EntityType::Function(FD_WRITE),
     );
+
+    self.types.function(vec![ValType::I32], Vec::new());
+    self.fn_map.insert("proc_exit".into(), PROC_EXIT);
+    self.imports.import(
+      self.target.wasi_module(),
+      Some("proc_exit"),
+      EntityType::Function(PROC_EXIT),
+    );
   }
 }
 
-/// Struct for an entry in an `iov` in WASI. It contains an offset to an item
-/// and the length of said item, usually used for printing strings.
-pub struct IoVecItem {
-  offset: i32,
-  len: i32,
+/// An entry in an `iov` in WASI. It points to an item in memory and holds the
+/// length of said item, usually used for printing strings.
+pub enum IoVecItem {
+  /// An item already in memory at a known offset
+  Static { offset: i32, len: i32 },
+  /// An integer that gets formatted as decimal text when the program runs
+  Int(Expr),
+  /// A bool that prints as `true` or `false`, pointing into the text
+  /// `truefalse` at `true_offset`
+  Bool { expr: Expr, true_offset: i32 },
+  /// A string made when the program runs
+  Str(Expr),
 }
 
 impl IoVecItem {
-  /// Create a new `IoVecItem`
+  /// Create a new `IoVecItem` for an item at a known offset
   pub fn new(offset: i32, len: i32) -> Self {
-    Self { offset, len }
+    Self::Static { offset, len }
   }
 }
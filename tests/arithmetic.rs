@@ -0,0 +1,28 @@
+mod common;
+
+use common::stdout;
+
+#[test]
+fn remainder_works_for_every_integer_type() {
+  let source = r#"
+    terminating state main() {
+      small <- 17 % 5;
+      big: i64 <- 5000000000 % 3;
+      negative: i64 <- -5000000000 % 7;
+      println("{small} {big} {negative}");
+    }
+  "#;
+  assert_eq!(stdout(source), "2 2 -2\n");
+}
+
+#[test]
+fn negative_literals_keep_their_full_range() {
+  let source = r#"
+    terminating state main() {
+      min <- -2147483648;
+      below <- -2147483649;
+      println("{min} {below}");
+    }
+  "#;
+  assert_eq!(stdout(source), "-2147483648 -2147483649\n");
+}
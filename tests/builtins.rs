@@ -0,0 +1,30 @@
+mod common;
+
+#[test]
+fn unused_builtins_are_left_out() {
+  let source = r#"
+    terminating state main() {
+      println("hello");
+    }
+  "#;
+  let wat = common::compile(source).wat().unwrap();
+  for builtin in ["__fmt_int", "__str_concat", "__str_eq", "__alloc", "__free"] {
+    assert!(!wat.contains(builtin), "`{}` was compiled in", builtin);
+  }
+  assert!(!wat.contains("memory.copy"));
+  assert_eq!(common::stdout(source), "hello\n");
+}
+
+#[test]
+fn used_builtins_are_compiled_in() {
+  let source = r#"
+    terminating state main() {
+      count <- 41 + 1;
+      println("count = {count}");
+    }
+  "#;
+  let wat = common::compile(source).wat().unwrap();
+  assert!(wat.contains("__fmt_int"));
+  assert!(!wat.contains("__alloc"));
+  assert_eq!(common::stdout(source), "count = 42\n");
+}
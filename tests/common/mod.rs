@@ -0,0 +1,42 @@
+//! Helpers shared by the integration tests
+#![allow(dead_code)]
+
+use libsyc::{
+  CompileOptions, CompiledModule, Compiler, OutputPipe, RunOptions, RunOutput, SycError, SycRuntime,
+};
+
+/// Compile a program with the default options, failing the test with the
+/// problems found in it if it doesn't compile
+pub fn compile(source: &str) -> CompiledModule {
+  compile_with(source, CompileOptions::default())
+}
+
+/// Compile a program with the given options, failing the test with the
+/// problems found in it if it doesn't compile
+pub fn compile_with(source: &str, options: CompileOptions) -> CompiledModule {
+  Compiler::new(options)
+    .compile_str(source, "test.sm")
+    .unwrap_or_else(|diagnostics| {
+      let mut report = Vec::new();
+      diagnostics.write(&mut report).unwrap();
+      panic!("{}\n{}", diagnostics, String::from_utf8_lossy(&report))
+    })
+}
+
+/// Run a compiled program with its standard output and error captured
+pub fn run(wasm: &[u8], options: RunOptions) -> Result<RunOutput, SycError> {
+  SycRuntime::new()?.run(
+    wasm,
+    RunOptions {
+      stdout: OutputPipe::Capture,
+      stderr: OutputPipe::Capture,
+      ..options
+    },
+  )
+}
+
+/// Compile and run a program, returning everything it printed
+pub fn stdout(source: &str) -> String {
+  let output = run(&compile(source).wasm, RunOptions::default()).unwrap();
+  String::from_utf8(output.stdout).unwrap()
+}
@@ -0,0 +1,79 @@
+mod common;
+
+use libsyc::{CompileOptions, Compiler, EmitKind, ErrorCode};
+
+#[test]
+fn problems_are_returned_instead_of_printed() {
+  let source = "terminating state main() {\n  x <- 1 + true;\n}\n";
+  let diagnostics = Compiler::default()
+    .compile_str(source, "bad.sm")
+    .unwrap_err();
+  assert!(diagnostics.has_errors());
+  let codes: Vec<_> = diagnostics.iter().map(|d| d.code).collect();
+  assert_eq!(codes, [ErrorCode::TypeMismatch]);
+  let mut report = Vec::new();
+  diagnostics.write(&mut report).unwrap();
+  assert!(String::from_utf8_lossy(&report).contains("bad.sm"));
+}
+
+#[test]
+fn warnings_are_kept_on_the_module() {
+  let source = r#"
+    terminating state main() {
+      terminate;
+    }
+    terminating state unused() {
+      terminate;
+    }
+  "#;
+  let module = common::compile(source);
+  let codes: Vec<_> = module.warnings.iter().map(|d| d.code).collect();
+  assert_eq!(codes, [ErrorCode::UnreachableState]);
+}
+
+#[test]
+fn check_finds_problems_without_compiling() {
+  let source = "terminating state main() { goto nowhere; }";
+  let (program, diagnostics) = Compiler::default().check_str(source, "check.sm");
+  assert_eq!(program.len(), 1);
+  let codes: Vec<_> = diagnostics.iter().map(|d| d.code).collect();
+  assert_eq!(codes, [ErrorCode::UnknownState]);
+}
+
+#[test]
+fn only_the_outputs_asked_for_are_kept() {
+  let source = r#"
+    terminating state main() {
+      println("hi");
+    }
+  "#;
+  let module = common::compile_with(
+    source,
+    CompileOptions {
+      emit: vec![EmitKind::Tokens, EmitKind::Wat],
+      ..CompileOptions::default()
+    },
+  );
+  let tokens = String::from_utf8_lossy(module.output(EmitKind::Tokens).unwrap());
+  assert!(tokens.contains("Terminating"), "{}", tokens);
+  let wat = String::from_utf8_lossy(module.output(EmitKind::Wat).unwrap());
+  assert!(wat.contains("(module"), "{}", wat);
+  assert!(module.output(EmitKind::Ast).is_none());
+  assert!(module.output(EmitKind::Wasm).is_none());
+}
+
+#[test]
+fn check_reports_a_missing_main() {
+  let source = "terminating state start() { terminate; }";
+  let (_, diagnostics) = Compiler::default().check_str(source, "check.sm");
+  let codes: Vec<_> = diagnostics.iter().map(|d| d.code).collect();
+  assert_eq!(codes, [ErrorCode::MissingMain]);
+}
+
+#[test]
+fn check_reports_a_main_that_cannot_end() {
+  let source = "state main() { goto main; }";
+  let (_, diagnostics) = Compiler::default().check_str(source, "check.sm");
+  let codes: Vec<_> = diagnostics.iter().map(|d| d.code).collect();
+  assert_eq!(codes, [ErrorCode::MainNotTerminating]);
+}
@@ -0,0 +1,32 @@
+mod common;
+
+use libsyc::Compiler;
+use std::{fs, path::Path};
+
+#[test]
+fn every_example_compiles_without_problems() {
+  let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples");
+  let mut count = 0;
+  for entry in fs::read_dir(dir).unwrap() {
+    let path = entry.unwrap().path();
+    if path.extension().map_or(true, |ext| ext != "sm") {
+      continue;
+    }
+    let source = fs::read_to_string(&path).unwrap();
+    let module = Compiler::default()
+      .compile_str(&source, &path.display().to_string())
+      .unwrap_or_else(|diagnostics| panic!("{}", diagnostics));
+    assert!(module.warnings.is_empty(), "{}", module.warnings);
+    count += 1;
+  }
+  assert!(count > 0);
+}
+
+#[test]
+fn strings_example_prints_joined_strings() {
+  let source = include_str!("../examples/strings.sm");
+  assert_eq!(
+    common::stdout(source),
+    "Hello, sycamore!\nsycamore is 8 bytes long\nfinished with: Hello, sycamore!\n"
+  );
+}
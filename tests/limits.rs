@@ -0,0 +1,109 @@
+mod common;
+
+use libsyc::{OutputPipe, RunOptions, SycError, SycRuntime};
+use std::time::Duration;
+use wasmtime::{Config, Engine};
+
+/// Counts up for far longer than any test wants to wait
+const SPIN: &str = r#"
+  terminating state main() {
+    goto spin(0);
+  }
+  terminating state spin(n: i32) {
+    if n equals 2000000000 {
+      terminate;
+    }
+    goto spin(n + 1);
+  }
+"#;
+
+#[test]
+fn running_out_of_fuel_stops_the_program() {
+  let wasm = common::compile(SPIN).wasm;
+  let error = SycRuntime::with_limits()
+    .unwrap()
+    .run(
+      &wasm,
+      RunOptions {
+        fuel: Some(10_000),
+        ..RunOptions::default()
+      },
+    )
+    .unwrap_err();
+  match error {
+    SycError::OutOfFuel { fuel, .. } => assert_eq!(fuel, 10_000),
+    error => panic!("expected the program to run out of fuel, got {}", error),
+  }
+}
+
+#[test]
+fn running_too_long_stops_the_program() {
+  let wasm = common::compile(SPIN).wasm;
+  let error = SycRuntime::with_limits()
+    .unwrap()
+    .run(
+      &wasm,
+      RunOptions {
+        timeout: Some(Duration::from_millis(50)),
+        ..RunOptions::default()
+      },
+    )
+    .unwrap_err();
+  assert!(matches!(error, SycError::Timeout { .. }), "{}", error);
+}
+
+#[test]
+fn limits_need_an_engine_that_can_enforce_them() {
+  let wasm = common::compile(SPIN).wasm;
+  let runtime = SycRuntime::with_engine(Engine::default()).unwrap();
+  for options in [
+    RunOptions {
+      fuel: Some(10_000),
+      ..RunOptions::default()
+    },
+    RunOptions {
+      timeout: Some(Duration::from_millis(50)),
+      ..RunOptions::default()
+    },
+  ] {
+    let error = runtime.run(&wasm, options).unwrap_err();
+    assert!(matches!(error, SycError::Link(_)), "{}", error);
+  }
+
+  let mut config = Config::new();
+  config.consume_fuel(true);
+  let runtime = SycRuntime::with_engine(Engine::new(&config).unwrap()).unwrap();
+  let error = runtime
+    .run(
+      &wasm,
+      RunOptions {
+        fuel: Some(10_000),
+        ..RunOptions::default()
+      },
+    )
+    .unwrap_err();
+  assert!(matches!(error, SycError::OutOfFuel { .. }), "{}", error);
+}
+
+#[test]
+fn programs_run_without_limits_on_any_engine() {
+  let source = r#"
+    terminating state main() {
+      println("done");
+    }
+  "#;
+  let wasm = common::compile(source).wasm;
+  for runtime in [SycRuntime::new(), SycRuntime::with_limits()] {
+    let output = runtime
+      .unwrap()
+      .run(
+        &wasm,
+        RunOptions {
+          stdout: OutputPipe::Capture,
+          ..RunOptions::default()
+        },
+      )
+      .unwrap();
+    assert_eq!(output.stdout, b"done\n");
+  }
+}
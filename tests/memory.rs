@@ -0,0 +1,60 @@
+mod common;
+
+use common::{compile, compile_with, run};
+use libsyc::{CompileOptions, RunOptions, SandboxPolicy};
+
+#[test]
+fn temporary_strings_are_freed() {
+  let source = r#"
+    terminating state main() {
+      goto repeat(10000);
+    }
+    terminating state repeat(n: i32) {
+      if n equals 0 {
+        terminate;
+      }
+      a <- "ab";
+      b <- "cd";
+      if a + b equals b + a {
+        println("never");
+      }
+      size <- len(a + b + a);
+      println("{a + b + a} {size}");
+      goto repeat(n - 1);
+    }
+  "#;
+  // The program starts out with two pages, so leaking any of the strings
+  // would run out of memory long before the loop is done
+  let module = compile_with(
+    source,
+    CompileOptions {
+      sandbox: SandboxPolicy {
+        max_memory_pages: Some(2),
+        ..SandboxPolicy::default()
+      },
+      ..CompileOptions::default()
+    },
+  );
+  let output = run(&module.wasm, RunOptions::default()).unwrap();
+  let stdout = String::from_utf8(output.stdout).unwrap();
+  assert_eq!(stdout.lines().count(), 10000);
+  assert!(stdout.lines().all(|line| line == "abcdab 6"));
+}
+
+#[test]
+fn memory_grows_to_fit_big_strings() {
+  let source = r#"
+    terminating state main() {
+      goto grow("ab", 0);
+    }
+    terminating state grow(s: string, n: i32) {
+      if n equals 17 {
+        println("{len(s)}");
+        terminate;
+      }
+      goto grow(s + s, n + 1);
+    }
+  "#;
+  let output = run(&compile(source).wasm, RunOptions::default()).unwrap();
+  assert_eq!(output.stdout, b"262144\n");
+}
@@ -0,0 +1,28 @@
+mod common;
+
+use libsyc::{OutputPipe, RunOptions, SycRuntime};
+
+#[test]
+fn a_loaded_program_runs_many_times() {
+  let source = r#"
+    terminating state main() {
+      println("bye");
+      terminate 3;
+    }
+  "#;
+  let runtime = SycRuntime::new().unwrap();
+  let program = runtime.load(&common::compile(source).wasm).unwrap();
+  for _ in 0..3 {
+    let output = runtime
+      .run_program(
+        &program,
+        RunOptions {
+          stdout: OutputPipe::Capture,
+          ..RunOptions::default()
+        },
+      )
+      .unwrap();
+    assert_eq!(output.exit_code, 3);
+    assert_eq!(output.stdout, b"bye\n");
+  }
+}
@@ -0,0 +1,116 @@
+mod common;
+
+use common::{compile_with, run};
+use libsyc::{Capability, CompileOptions, RunOptions, SandboxOverrides, SandboxPolicy, SycError};
+
+/// Compile a program with the given sandbox
+fn compile_in(source: &str, sandbox: SandboxPolicy) -> Vec<u8> {
+  compile_with(
+    source,
+    CompileOptions {
+      sandbox,
+      ..CompileOptions::default()
+    },
+  )
+  .wasm
+}
+
+const HELLO: &str = r#"
+  terminating state main() {
+    println("hello");
+    terminate 3;
+  }
+"#;
+
+/// Doubles a string until memory runs out
+const GROW: &str = r#"
+  terminating state main() {
+    goto grow("ab");
+  }
+  terminating state grow(s: string) {
+    if len(s) equals 0 {
+      terminate;
+    }
+    goto grow(s + s);
+  }
+"#;
+
+#[test]
+fn denied_capability_is_left_out() {
+  let wasm = compile_in(
+    HELLO,
+    SandboxPolicy {
+      capabilities: vec![Capability::Stderr],
+      ..SandboxPolicy::default()
+    },
+  );
+  let output = run(&wasm, RunOptions::default()).unwrap();
+  assert_eq!(output.exit_code, 3);
+  assert_eq!(output.stdout, b"");
+}
+
+#[test]
+fn overrides_keep_the_rest_of_the_compiled_sandbox() {
+  let wasm = compile_in(
+    HELLO,
+    SandboxPolicy {
+      capabilities: vec![Capability::Stderr],
+      ..SandboxPolicy::default()
+    },
+  );
+  let output = run(
+    &wasm,
+    RunOptions {
+      sandbox_overrides: SandboxOverrides {
+        max_memory_pages: Some(16),
+        ..SandboxOverrides::default()
+      },
+      ..RunOptions::default()
+    },
+  )
+  .unwrap();
+  assert_eq!(output.stdout, b"");
+
+  let output = run(
+    &wasm,
+    RunOptions {
+      sandbox_overrides: SandboxOverrides {
+        capabilities: Some(vec![Capability::Stdout]),
+        ..SandboxOverrides::default()
+      },
+      ..RunOptions::default()
+    },
+  )
+  .unwrap();
+  assert_eq!(output.stdout, b"hello\n");
+}
+
+#[test]
+fn memory_cap_is_enforced() {
+  let wasm = compile_in(
+    GROW,
+    SandboxPolicy {
+      max_memory_pages: Some(4),
+      ..SandboxPolicy::default()
+    },
+  );
+  let error = run(&wasm, RunOptions::default()).unwrap_err();
+  assert!(matches!(error, SycError::Trap(_)), "{}", error);
+}
+
+#[test]
+fn memory_cap_can_be_given_when_running() {
+  let wasm = compile_in(GROW, SandboxPolicy::default());
+  let error = run(
+    &wasm,
+    RunOptions {
+      sandbox_overrides: SandboxOverrides {
+        max_memory_pages: Some(4),
+        ..SandboxOverrides::default()
+      },
+      ..RunOptions::default()
+    },
+  )
+  .unwrap_err();
+  assert!(matches!(error, SycError::Trap(_)), "{}", error);
+}
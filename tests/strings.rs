@@ -0,0 +1,25 @@
+mod common;
+
+use libsyc::RunOptions;
+
+#[test]
+fn escapes_print_the_characters_they_stand_for() {
+  let source = r#"
+    terminating state main() {
+      print("a\tb\n\u{e9}");
+    }
+  "#;
+  let output = common::run(&common::compile(source).wasm, RunOptions::default()).unwrap();
+  assert_eq!(output.stdout, "a\tb\n\u{e9}".as_bytes());
+}
+
+#[test]
+fn len_counts_utf8_bytes() {
+  let source = r#"
+    terminating state main() {
+      word <- "caf\u{e9}";
+      println("{len(word)}");
+    }
+  "#;
+  assert_eq!(common::stdout(source), "5\n");
+}
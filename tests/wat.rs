@@ -0,0 +1,78 @@
+mod common;
+
+use libsyc::{EmitKind, Output, RunOptions};
+use std::{env, fs, path::PathBuf, process};
+
+/// A directory of its own for a test to write files into, removed once the
+/// test is done with it
+struct TempDir(PathBuf);
+
+impl TempDir {
+  fn new(name: &str) -> Self {
+    let dir = env::temp_dir().join(format!("syc-{}-{}", name, process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    Self(dir)
+  }
+}
+
+impl Drop for TempDir {
+  fn drop(&mut self) {
+    let _ = fs::remove_dir_all(&self.0);
+  }
+}
+
+const CONCAT: &str = r#"
+  terminating state main() {
+    greeting <- "hello" + ", " + "world";
+    println(greeting);
+  }
+"#;
+
+#[test]
+fn strings_can_be_shown_as_wat() {
+  let wat = common::compile(CONCAT).wat().unwrap();
+  assert!(wat.contains("memory.copy"));
+}
+
+#[test]
+fn emitted_wat_builds_again() {
+  let dir = TempDir::new("emitted-wat");
+  let source = dir.0.join("concat.sm");
+  fs::write(&source, CONCAT).unwrap();
+  libsyc::build(&source, false, &[EmitKind::Wat], &Output::NextToSource).unwrap();
+
+  let wat = dir.0.join("concat.wat");
+  assert!(fs::read_to_string(&wat).unwrap().contains("memory.copy"));
+  let wasm = libsyc::build(&wat, false, &[], &Output::NextToSource).unwrap();
+  let output = common::run(&wasm, RunOptions::default()).unwrap();
+  assert_eq!(output.stdout, b"hello, world\n");
+}
+
+#[test]
+fn hand_written_wat_can_use_bulk_memory() {
+  let dir = TempDir::new("hand-written-wat");
+  let source = dir.0.join("copy.wat");
+  fs::write(
+    &source,
+    r#"
+    (module
+      (import "wasi_unstable" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+      (memory 1)
+      (export "memory" (memory 0))
+      (data (i32.const 16) "copied\n")
+      (func $_start
+        ;; Copy the text and point an iov at the copy
+        (memory.copy (i32.const 64) (i32.const 16) (i32.const 7))
+        (i32.store (i32.const 0) (i32.const 64))
+        (i32.store (i32.const 4) (i32.const 7))
+        (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8))))
+      (export "_start" (func $_start)))
+    "#,
+  )
+  .unwrap();
+  let out = dir.0.join("out");
+  let wasm = libsyc::build(&source, false, &[EmitKind::Wasm], &Output::Dir(out.clone())).unwrap();
+  assert_eq!(fs::read(out.join("copy.csm")).unwrap(), wasm);
+  let output = common::run(&wasm, RunOptions::default()).unwrap();
+  assert_eq!(output.stdout, b"copied\n");
+}